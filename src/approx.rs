@@ -0,0 +1,103 @@
+use std::{collections::HashSet, fmt::Display};
+
+use crate::{bag::Bag, pattern::Pattern, queue::Queue, ratio::Ratio};
+
+// How many queues of the universe a found pattern may get wrong
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Tolerance {
+    // queues outside of the set that the pattern matches anyway
+    pub false_positives: usize,
+    // queues of the set that the pattern misses
+    pub false_negatives: usize,
+}
+
+impl Tolerance {
+    pub fn is_exact(&self) -> bool {
+        self.false_positives == 0 && self.false_negatives == 0
+    }
+
+    pub fn allows(&self, false_positives: usize, false_negatives: usize) -> bool {
+        false_positives <= self.false_positives && false_negatives <= self.false_negatives
+    }
+}
+
+// How well a pattern separates a set from the rest of a universe
+#[derive(Clone, Debug, PartialEq)]
+pub struct Report {
+    pub true_positives: usize,
+    pub false_positives: Vec<Queue>,
+    pub false_negatives: Vec<Queue>,
+}
+
+impl Report {
+    // The share of matched queues that are in the set; a pattern matching nothing is never wrong
+    pub fn precision(&self) -> Ratio {
+        let matched = self.true_positives + self.false_positives.len();
+        if matched == 0 {
+            return Ratio::ONE;
+        }
+
+        Ratio::new(self.true_positives as u128, matched as u128)
+    }
+
+    // The share of the set that got matched
+    pub fn recall(&self) -> Ratio {
+        let wanted = self.true_positives + self.false_negatives.len();
+        if wanted == 0 {
+            return Ratio::ONE;
+        }
+
+        Ratio::new(self.true_positives as u128, wanted as u128)
+    }
+}
+
+impl<B> Pattern<B>
+where
+    B: Bag,
+{
+    // Compares what this pattern matches against `set`, within `universe` (and `set` itself)
+    pub fn report(&self, universe: &[Queue], set: &[Queue]) -> Report {
+        let matched = self.set();
+        let wanted: HashSet<&Queue> = set.iter().collect();
+        let mut all: Vec<&Queue> = universe.iter().chain(set).collect();
+        all.sort();
+        all.dedup();
+
+        let mut report = Report {
+            true_positives: 0,
+            false_positives: vec![],
+            false_negatives: vec![],
+        };
+        for q in all {
+            match (matched.contains(q), wanted.contains(q)) {
+                (true, true) => report.true_positives += 1,
+                (true, false) => report.false_positives.push(q.clone()),
+                (false, true) => report.false_negatives.push(q.clone()),
+                (false, false) => {}
+            }
+        }
+
+        report
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let list = |x: &[Queue]| {
+            if x.is_empty() {
+                "none".to_string()
+            } else {
+                x.iter()
+                    .map(|q| format!("{q:?}"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            }
+        };
+
+        let (p, r) = (self.precision(), self.recall());
+        writeln!(f, "precision {p} ({:.4}%)", p.to_f64() * 100.0)?;
+        writeln!(f, "recall {r} ({:.4}%)", r.to_f64() * 100.0)?;
+        writeln!(f, "false positives: {}", list(&self.false_positives))?;
+        write!(f, "false negatives: {}", list(&self.false_negatives))
+    }
+}
@@ -1,4 +1,4 @@
-use std::fmt::Debug;
+use std::{fmt::Debug, sync::OnceLock};
 
 pub trait Bag
 where
@@ -19,3 +19,50 @@ impl Bag for Bag7 {
         vec!['T', 'I', 'L', 'J', 'O', 'S', 'Z']
     }
 }
+
+// characters the pattern grammar gives meaning to, which can't double as pieces
+const RESERVED: &str = "*()[]{}<>;,!?^#@=&|.-p";
+
+static PIECES: OnceLock<Vec<char>> = OnceLock::new();
+
+// A piece set chosen at runtime, falling back to the pieces of `Bag7`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pieces;
+impl Pieces {
+    pub fn define(pieces: &str) -> Result<(), String> {
+        let mut v = vec![];
+        for c in pieces.chars().filter(|c| !c.is_whitespace()) {
+            if RESERVED.contains(c) || c.is_ascii_digit() {
+                return Err(format!(
+                    "{c:?} is part of the pattern syntax and can't be a piece"
+                ));
+            }
+
+            if v.contains(&c) {
+                return Err(format!("piece {c:?} is listed more than once"));
+            }
+
+            v.push(c);
+        }
+
+        if v.is_empty() {
+            return Err("no pieces given".to_string());
+        }
+
+        PIECES
+            .set(v)
+            .map_err(|_| "pieces were already defined".to_string())
+    }
+}
+
+impl Bag for Pieces {
+    fn has(piece: char) -> bool {
+        PIECES
+            .get()
+            .map_or_else(|| Bag7::has(piece), |v| v.contains(&piece))
+    }
+
+    fn wildcard() -> Vec<char> {
+        PIECES.get().cloned().unwrap_or_else(Bag7::wildcard)
+    }
+}
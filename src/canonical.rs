@@ -0,0 +1,322 @@
+use itertools::Itertools;
+
+use crate::{bag::Bag, condition::Condition, pattern::Pattern};
+
+// How loosely each kind of pattern binds when printed, from atoms up to `;`
+pub fn rank<B: Bag>(p: &Pattern<B>) -> usize {
+    match p {
+        Pattern::Single(..)
+        | Pattern::Wildcard
+        | Pattern::Any(..)
+        | Pattern::Except(..)
+        | Pattern::Group(..) => 0,
+        Pattern::All(..) => 1,
+        Pattern::Take(..) => 2,
+        Pattern::Condition(..) => 3,
+        Pattern::Seq(..) => 4,
+        Pattern::Unique(..) => 5,
+        Pattern::Either(..) => 6,
+    }
+}
+
+// The same for conditions, from atoms up to `|`
+fn condition_rank<B: Bag>(c: &Condition<B>) -> usize {
+    match c {
+        Condition::Not(..) => 1,
+        Condition::And(..) => 2,
+        Condition::Or(..) => 3,
+        _ => 0,
+    }
+}
+
+impl<B> Pattern<B>
+where
+    B: Bag,
+{
+    // A normal form: `;` and sequences nested to the left, piece sets sorted by bag order without
+    // repeats (unless a `#` could count differently for it), conditions normalized, and groups only where printing needs them. Two patterns
+    // that differ only in how they were written compare equal once canonicalized
+    pub fn canonicalize(&self) -> Self {
+        self.normalize(true)
+    }
+
+    // The canonical form, with piece sets left in the order they were written unless `sort`
+    fn normalize(&self, sort: bool) -> Self {
+        match self {
+            Self::Group(p) => p.normalize(sort),
+            Self::Single(c) => Self::Single(*c),
+            Self::Wildcard => Self::Wildcard,
+            Self::Either(..) => {
+                let mut parts = vec![];
+                self.flatten(false, sort, &mut parts);
+                Self::rebuild(parts, Self::Either, 6, 5)
+            }
+            Self::Seq(..) => {
+                let mut parts = vec![];
+                self.flatten(true, sort, &mut parts);
+                Self::rebuild(parts, Self::Seq, 4, 3)
+            }
+            Self::Any(t) => Self::Any(Self::members(t, true, sort, true)),
+            Self::Except(t) => Self::Except(Self::members(t, false, sort, true)),
+            Self::All(p) => Self::All(Box::new(p.normalize_drawn(sort).wrap(0))),
+            Self::Take(p, n) => Self::Take(Box::new(p.normalize_drawn(sort).wrap(1)), *n),
+            Self::Condition(p, c) => Self::Condition(
+                Box::new(p.normalize(sort).wrap(2)),
+                Box::new(c.canonicalize()),
+            ),
+            Self::Unique(p) => Self::Unique(Box::new(p.normalize(sort).wrap(4))),
+        }
+    }
+
+    // The same, for what a take draws from: there a repeated piece can be drawn more than once,
+    // so repeats are kept
+    fn normalize_drawn(&self, sort: bool) -> Self {
+        match self {
+            Self::Group(p) => p.normalize_drawn(sort),
+            Self::Any(t) => Self::Any(Self::members(t, true, sort, false)),
+            x => x.normalize(sort),
+        }
+    }
+
+    // The length every queue of this pattern has, if they all have the same one. `#` counts
+    // with whichever queue of its pattern matches first, so only then does their order not
+    // matter
+    pub fn fixed_len(&self) -> Option<usize> {
+        match self {
+            Self::Single(..) | Self::Wildcard | Self::Except(..) => Some(1),
+            Self::Group(p) | Self::Condition(p, _) | Self::Unique(p) => p.fixed_len(),
+            Self::Either(a, b) => a.fixed_len().filter(|x| b.fixed_len() == Some(*x)),
+            Self::Seq(a, b) => Some(a.fixed_len()? + b.fixed_len()?),
+            Self::Any(t) => t.iter().map(|x| x.fixed_len()).all_equal_value().ok()?,
+            Self::Take(p, n) => Some(p.fixed_len()? * n),
+            Self::All(p) => Some(p.fixed_len()? * p.count()),
+        }
+    }
+
+    // Groups this pattern if it would otherwise bind looser than `max` allows
+    fn wrap(self, max: usize) -> Self {
+        if rank(&self) > max {
+            Self::Group(Box::new(self))
+        } else {
+            self
+        }
+    }
+
+    // The canonical operands of a chain of sequences (or of `;`), left to right
+    fn flatten(&self, seq: bool, sort: bool, parts: &mut Vec<Self>) {
+        match self {
+            Self::Group(p) => p.flatten(seq, sort, parts),
+            Self::Seq(a, b) if seq => {
+                a.flatten(seq, sort, parts);
+                b.flatten(seq, sort, parts);
+            }
+            Self::Either(a, b) if !seq => {
+                a.flatten(seq, sort, parts);
+                b.flatten(seq, sort, parts);
+            }
+            x => parts.push(x.normalize(sort)),
+        }
+    }
+
+    // Folds operands back up to the left, the way the parser does
+    fn rebuild(
+        parts: Vec<Self>,
+        join: fn(Box<Self>, Box<Self>) -> Self,
+        left: usize,
+        right: usize,
+    ) -> Self {
+        let mut parts = parts.into_iter();
+        let first = parts.next().expect("a chain has at least one operand");
+        parts.fold(first.wrap(left), |a, b| {
+            join(Box::new(a), Box::new(b.wrap(right)))
+        })
+    }
+
+    // Members of `[...]` or `[^...]`: with `sort`, pieces in bag order first, then anything
+    // else; each once with `dedup`
+    fn members(t: &[Self], flatten: bool, sort: bool, dedup: bool) -> Vec<Self> {
+        let mut flat = vec![];
+        for x in t.iter().map(|x| x.normalize(sort)) {
+            match x {
+                Self::Any(inner) if flatten => flat.extend(inner),
+                x => flat.push(x),
+            }
+        }
+
+        if sort {
+            let order = B::wildcard();
+            let (mut pieces, rest): (Vec<_>, Vec<_>) = flat
+                .into_iter()
+                .partition(|x| matches!(x, Self::Single(..)));
+            pieces.sort_by_key(|x| match x {
+                Self::Single(c) => order.iter().position(|y| y == c),
+                _ => None,
+            });
+            flat = pieces.into_iter().chain(rest).collect();
+        }
+
+        // a later repeat never gets to match first, so dropping it is safe even unsorted
+        let mut v: Vec<Self> = vec![];
+        for x in flat {
+            let x = match x {
+                Self::Single(..) | Self::Wildcard => x,
+                x => Self::Group(Box::new(x)),
+            };
+            if !dedup || !v.contains(&x) {
+                v.push(x);
+            }
+        }
+
+        v
+    }
+}
+
+impl<B> Condition<B>
+where
+    B: Bag,
+{
+    // A normal form: `>` turned around into `<`, double negations dropped, `&` and `|` chains
+    // sorted without repeats, places sorted without repeats, and canonical patterns throughout
+    pub fn canonicalize(&self) -> Self {
+        // patterns inside conditions are parsed at the level of takes
+        let operand = |p: &Pattern<B>| p.canonicalize().wrap(2);
+        match self {
+            Self::Group(c) => c.canonicalize(),
+            Self::Before(a, b) => Self::Before(operand(a), operand(b)),
+            Self::After(a, b) => Self::Before(operand(b), operand(a)),
+            // counting can depend on the order of its pattern's queues
+            Self::Count(a, n) => match a.fixed_len() {
+                Some(_) => Self::Count(operand(a), *n),
+                None => Self::Count(a.normalize(false).wrap(2), *n),
+            },
+            Self::Location(a, places) => {
+                let mut places = places.clone();
+                places.sort_by_key(|x| x.to_string());
+                places.dedup();
+                Self::Location(operand(a), places)
+            }
+            Self::Not(c) => match c.canonicalize() {
+                Self::Not(x) => x.unwrap_group(),
+                x => Self::Not(Box::new(x.wrap(1))),
+            },
+            Self::And(..) | Self::Or(..) => {
+                let and = matches!(self, Self::And(..));
+                let mut parts = vec![];
+                self.flatten(and, &mut parts);
+                parts.sort_by_key(|x| x.to_string());
+                parts.dedup();
+
+                let mut parts = parts.into_iter();
+                let first = parts.next().expect("a chain has at least one operand");
+                if and {
+                    parts.fold(first.wrap(2), |a, b| {
+                        Self::And(Box::new(a), Box::new(b.wrap(1)))
+                    })
+                } else {
+                    parts.fold(first.wrap(3), |a, b| {
+                        Self::Or(Box::new(a), Box::new(b.wrap(2)))
+                    })
+                }
+            }
+            Self::Phantom(p) => Self::Phantom(*p),
+        }
+    }
+
+    fn flatten(&self, and: bool, parts: &mut Vec<Self>) {
+        match self {
+            Self::Group(c) => c.flatten(and, parts),
+            Self::And(a, b) if and => {
+                a.flatten(and, parts);
+                b.flatten(and, parts);
+            }
+            Self::Or(a, b) if !and => {
+                a.flatten(and, parts);
+                b.flatten(and, parts);
+            }
+            x => parts.push(x.canonicalize()),
+        }
+    }
+
+    fn unwrap_group(self) -> Self {
+        match self {
+            Self::Group(c) => *c,
+            x => x,
+        }
+    }
+
+    fn wrap(self, max: usize) -> Self {
+        if condition_rank(&self) > max {
+            Self::Group(Box::new(self))
+        } else {
+            self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::marker::PhantomData;
+
+    use crate::{
+        bag::Bag7,
+        pattern::Pattern,
+        rule::{Rule, dedup_either::DedupEither},
+    };
+
+    const PATTERNS: [&str; 12] = [
+        "T(I(OS))",
+        "((T;I);(O;S))Z",
+        "[OTIT]2",
+        "[^SZS]!",
+        "(TI)2;(IT)2",
+        "*p3{O>T&(I<S|#Z=1)}",
+        "*p3{!!T<I}",
+        "[TI](*p2{@O=-1,0,-1});S",
+        "(T;I)?",
+        "((T*)2){#T<=1}",
+        "(TTI){#[(TT)T]=1}",
+        "*p4{#(T;TT;[IT]I)=2}",
+    ];
+
+    #[test]
+    fn idempotent() {
+        for text in PATTERNS {
+            let once = Pattern::<Bag7>::new(text).unwrap().canonicalize();
+            assert_eq!(once.canonicalize(), once, "{text}");
+        }
+    }
+
+    #[test]
+    fn keeps_queues() {
+        for text in PATTERNS {
+            let p = Pattern::<Bag7>::new(text).unwrap();
+            let c = p.canonicalize();
+            assert!(p.equivalent(&c), "{text} vs {c}");
+            assert_eq!(Pattern::<Bag7>::new(c.to_string()).unwrap(), c, "{text}");
+        }
+    }
+
+    #[test]
+    fn writings_of_the_same_pattern_agree() {
+        for (a, b) in [
+            ("T(IO)", "(TI)O"),
+            ("[OT]", "[TOT]"),
+            ("T;(I;O)", "(T;I);O"),
+            ("*p2{I>T}", "*p2{(T<I)}"),
+            ("*p2{O<S&T<I}", "*p2{T<I&O<S}"),
+            ("*p3{#[IT]=1}", "*p3{#[TI]=1}"),
+        ] {
+            let a = Pattern::<Bag7>::new(a).unwrap().canonicalize();
+            let b = Pattern::<Bag7>::new(b).unwrap().canonicalize();
+            assert_eq!(a, b);
+        }
+    }
+
+    #[test]
+    fn dedup_either_sees_through_writing() {
+        let p = Pattern::<Bag7>::new("T(IO);(TI)O").unwrap();
+        let q = DedupEither(PhantomData).apply(&p);
+        assert!(p.equivalent(&q));
+        assert!(q.count() < p.count(), "{q}");
+    }
+}
@@ -15,13 +15,14 @@ pub enum Condition<B>
 where
     B: Bag,
 {
-    Before(Pattern<B>, Pattern<B>), // A<B
-    After(Pattern<B>, Pattern<B>),  // A>B
-    Group(Box<Self>),               // (A)
-    Count(Pattern<B>, usize),       // #A=N
-    Location(Pattern<B>, usize),    // @A=N
-    And(Box<Self>, Box<Self>),      // A&B
-    Or(Box<Self>, Box<Self>),       // A|B
+    Before(Pattern<B>, Pattern<B>),   // A<B
+    After(Pattern<B>, Pattern<B>),    // A>B
+    Group(Box<Self>),                 // (A)
+    Count(Pattern<B>, Compare),       // #A=N, #A<=N, #A=N..M
+    Location(Pattern<B>, Vec<Place>), // @A=N, @A=-1, @A=N..M, @A=N,M
+    And(Box<Self>, Box<Self>),        // A&B
+    Or(Box<Self>, Box<Self>),         // A|B
+    Not(Box<Self>),                   // !A
     // idk why but commenting this out causes like 600 type errors
     Phantom(PhantomData<B>),
 }
@@ -37,30 +38,40 @@ where
         B: 'a,
     {
         recursive(|a| {
-            let number = digits(10)
-                .collect::<String>()
-                .from_str::<usize>()
-                .unwrapped();
             let before = group((recurse.clone(), just('<'), recurse.clone()))
                 .map(|(a, _, b)| Self::Before(a, b));
             let after = group((recurse.clone(), just('>'), recurse.clone()))
                 .map(|(a, _, b)| Self::After(a, b));
-            let count = group((just('#'), recurse.clone(), just('='), number))
-                .map(|(_, p, _, n)| Self::Count(p, n));
-            let location = group((just('@'), recurse.clone(), just('='), number))
-                .map(|(_, p, _, n)| Self::Location(p, n));
+            let count = group((just('#'), recurse.clone(), Compare::parser()))
+                .map(|(_, p, n)| Self::Count(p, n));
+            let location = group((
+                just('@'),
+                recurse.clone(),
+                just('='),
+                Place::parser()
+                    .separated_by(just(','))
+                    .at_least(1)
+                    .collect(),
+            ))
+            .map(|(_, p, _, n)| Self::Location(p, n));
 
             let gr = a
                 .delimited_by(just('('), just(')'))
                 .map(|x| Self::Group(Box::new(x)));
             let atom = choice((count, location, before, after, gr)).boxed();
 
-            let and = atom
+            // `!` binds tighter than `&`, which binds tighter than `|`
+            let not = just('!')
+                .repeated()
+                .foldr(atom.clone(), |_, a| Self::Not(Box::new(a)))
+                .boxed();
+
+            let and = not
                 .clone()
-                .foldl(just('&').then(atom.clone()).repeated(), |a, (_, b)| {
+                .foldl(just('&').then(not.clone()).repeated(), |a, (_, b)| {
                     Self::And(Box::new(a), Box::new(b))
                 })
-                .or(atom.clone());
+                .or(not.clone());
 
             let or = and
                 .clone()
@@ -69,7 +80,7 @@ where
                 })
                 .or(and.clone());
 
-            choice((or, and, atom)).boxed()
+            choice((or, and, not)).boxed()
         })
     }
 
@@ -82,12 +93,12 @@ where
 
                 for start in 0..=queue.len() {
                     for aq in a.queues() {
-                        if queue.vec()[start..].starts_with(&aq.vec()) {
+                        if queue.vec()[start..].starts_with(aq.vec()) {
                             earliest_a = Some(start.min(earliest_a.unwrap_or(usize::MAX)));
                         }
                     }
                     for bq in b.queues() {
-                        if queue.vec()[start..].starts_with(&bq.vec()) {
+                        if queue.vec()[start..].starts_with(bq.vec()) {
                             earliest_b = Some(start.min(earliest_b.unwrap_or(usize::MAX)));
                         }
                     }
@@ -106,7 +117,7 @@ where
                 while start < queue.len() {
                     let mut matched = false;
                     for pq in pat.queues() {
-                        if queue.vec()[start..].starts_with(&pq.vec()) {
+                        if queue.vec()[start..].starts_with(pq.vec()) {
                             count += 1;
                             start += pq.len();
                             matched = true;
@@ -117,21 +128,17 @@ where
                         start += 1;
                     }
                 }
-                count == *n
+                n.test(count)
             }
             Self::And(a, b) => a.has(queue) && b.has(queue),
             Self::Or(a, b) => a.has(queue) || b.has(queue),
+            Self::Not(a) => !a.has(queue),
             Self::Phantom(..) => unsafe { std::hint::unreachable_unchecked() },
-            Self::Location(pat, n) => {
-                if *n > queue.len() {
-                    return false;
-                }
-                for pq in pat.queues() {
-                    if queue.vec()[*n..].starts_with(&pq.vec()) {
-                        return true;
-                    }
-                }
-                false
+            Self::Location(pat, places) => {
+                let pqs = pat.queues();
+                (0..=queue.len())
+                    .filter(|&n| places.iter().any(|p| p.contains(n, queue.len())))
+                    .any(|n| pqs.iter().any(|pq| queue.vec()[n..].starts_with(pq.vec())))
             }
         }
     }
@@ -141,7 +148,7 @@ where
             Self::After(a, b) | Self::Before(a, b) => 1 + a.size() + b.size(),
             Self::And(a, b) | Self::Or(a, b) => 1 + a.size() + b.size(),
             Self::Count(a, ..) | Self::Location(a, ..) => 1 + a.size(),
-            Self::Group(a) => 1 + a.size(),
+            Self::Group(a) | Self::Not(a) => 1 + a.size(),
             Self::Phantom(..) => 0,
         }
     }
@@ -159,12 +166,276 @@ where
                 Self::Phantom(..) => unsafe { std::hint::unreachable_unchecked() },
                 Self::Before(a, b) => format!("{a}<{b}"),
                 Self::After(a, b) => format!("{a}>{b}"),
-                Self::Count(a, b) => format!("#{a}={b}"),
+                Self::Count(a, b) => format!("#{a}{b}"),
                 Self::And(a, b) => format!("{a}&{b}"),
                 Self::Or(a, b) => format!("{a}|{b}"),
                 Self::Group(p) => format!("({p})"),
-                Self::Location(a, b) => format!("@{a}={b}"),
+                Self::Not(p) => format!("!{p}"),
+                Self::Location(a, b) => format!(
+                    "@{a}={}",
+                    b.iter()
+                        .map(|x| x.to_string())
+                        .collect::<Vec<_>>()
+                        .join(",")
+                ),
             }
         )
     }
 }
+
+// How an occurrence count is compared against constants; ranges exclude their end like Rust's
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compare {
+    Eq(usize),                    // =N
+    Ne(usize),                    // !=N
+    Lt(usize),                    // <N
+    Le(usize),                    // <=N
+    Gt(usize),                    // >N
+    Ge(usize),                    // >=N
+    Range(usize, usize),          // =N..M
+    RangeInclusive(usize, usize), // =N..=M
+}
+
+impl Compare {
+    pub fn parser<'a>() -> impl Parser<'a, &'a str, Self, Err<Rich<'a, char>>> + Clone {
+        let number = || {
+            digits(10).collect::<String>().try_map(|x, span| {
+                x.parse::<usize>()
+                    .map_err(|e| Rich::custom(span, format!("{x}: {e}")))
+            })
+        };
+
+        let range = group((number(), just(".."), just('=').or_not(), number())).map(
+            |(a, _, inclusive, b)| match inclusive {
+                Some(_) => Self::RangeInclusive(a, b),
+                None => Self::Range(a, b),
+            },
+        );
+
+        choice((
+            just("<=").ignore_then(number()).map(Self::Le),
+            just(">=").ignore_then(number()).map(Self::Ge),
+            just("!=").ignore_then(number()).map(Self::Ne),
+            just('<').ignore_then(number()).map(Self::Lt),
+            just('>').ignore_then(number()).map(Self::Gt),
+            just('=').ignore_then(range.or(number().map(Self::Eq))),
+        ))
+        .boxed()
+    }
+
+    pub fn test(&self, n: usize) -> bool {
+        match *self {
+            Self::Eq(x) => n == x,
+            Self::Ne(x) => n != x,
+            Self::Lt(x) => n < x,
+            Self::Le(x) => n <= x,
+            Self::Gt(x) => n > x,
+            Self::Ge(x) => n >= x,
+            Self::Range(a, b) => (a..b).contains(&n),
+            Self::RangeInclusive(a, b) => (a..=b).contains(&n),
+        }
+    }
+
+    // The largest constant involved; every count above it compares the same way
+    pub fn max(&self) -> usize {
+        match *self {
+            Self::Eq(x) | Self::Ne(x) | Self::Lt(x) | Self::Le(x) | Self::Gt(x) | Self::Ge(x) => x,
+            Self::Range(a, b) | Self::RangeInclusive(a, b) => a.max(b),
+        }
+    }
+}
+
+impl Display for Compare {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Eq(x) => write!(f, "={x}"),
+            Self::Ne(x) => write!(f, "!={x}"),
+            Self::Lt(x) => write!(f, "<{x}"),
+            Self::Le(x) => write!(f, "<={x}"),
+            Self::Gt(x) => write!(f, ">{x}"),
+            Self::Ge(x) => write!(f, ">={x}"),
+            Self::Range(a, b) => write!(f, "={a}..{b}"),
+            Self::RangeInclusive(a, b) => write!(f, "={a}..={b}"),
+        }
+    }
+}
+
+// A set of queue indices; negative indices count back from the end of each queue
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Place {
+    At(isize),                    // N, -N
+    Range(isize, Option<isize>),  // N..M, N..
+    RangeInclusive(isize, isize), // N..=M
+}
+
+impl Place {
+    pub fn parser<'a>() -> impl Parser<'a, &'a str, Self, Err<Rich<'a, char>>> + Clone {
+        let int = || {
+            just('-')
+                .or_not()
+                .then(digits(10).collect::<String>().try_map(|x, span| {
+                    x.parse::<isize>()
+                        .map_err(|e| Rich::custom(span, format!("{x}: {e}")))
+                }))
+                .map(|(sign, n)| if sign.is_some() { -n } else { n })
+        };
+
+        choice((
+            group((int(), just("..="), int())).map(|(a, _, b)| Self::RangeInclusive(a, b)),
+            group((int(), just(".."), int().or_not())).map(|(a, _, b)| Self::Range(a, b)),
+            int().map(Self::At),
+        ))
+        .boxed()
+    }
+
+    fn resolve(i: isize, len: usize) -> isize {
+        if i < 0 { len as isize + i } else { i }
+    }
+
+    pub fn contains(&self, n: usize, len: usize) -> bool {
+        let n = n as isize;
+        match *self {
+            Self::At(i) => Self::resolve(i, len) == n,
+            Self::Range(a, b) => {
+                Self::resolve(a, len) <= n && n < b.map_or(len as isize, |b| Self::resolve(b, len))
+            }
+            Self::RangeInclusive(a, b) => Self::resolve(a, len) <= n && n <= Self::resolve(b, len),
+        }
+    }
+}
+
+impl Display for Place {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::At(i) => write!(f, "{i}"),
+            Self::Range(a, Some(b)) => write!(f, "{a}..{b}"),
+            Self::Range(a, None) => write!(f, "{a}.."),
+            Self::RangeInclusive(a, b) => write!(f, "{a}..={b}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        bag::Bag7,
+        condition::{Compare, Condition, Place},
+        pattern::Pattern,
+    };
+
+    // the condition of `text`, after checking it prints back the way it was written
+    fn parse(text: &str) -> Condition<Bag7> {
+        let p = Pattern::<Bag7>::new(text).unwrap();
+        assert_eq!(p.to_string(), text);
+        assert_eq!(Pattern::<Bag7>::new(p.to_string()).unwrap(), p);
+        match p {
+            Pattern::Condition(_, c) => *c,
+            _ => panic!("{text} is not a condition"),
+        }
+    }
+
+    #[test]
+    fn comparisons() {
+        for (text, compare) in [
+            ("*3{#T=1}", Compare::Eq(1)),
+            ("*3{#T!=1}", Compare::Ne(1)),
+            ("*3{#T<1}", Compare::Lt(1)),
+            ("*3{#T<=1}", Compare::Le(1)),
+            ("*3{#T>1}", Compare::Gt(1)),
+            ("*3{#T>=1}", Compare::Ge(1)),
+            ("*3{#T=1..3}", Compare::Range(1, 3)),
+            ("*3{#T=1..=3}", Compare::RangeInclusive(1, 3)),
+        ] {
+            assert!(
+                matches!(parse(text), Condition::Count(_, x) if x == compare),
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn not_equal_is_not_all() {
+        // `[TI]!=1` compares a count, while `[TI]!` on its own is every ordering of `[TI]`
+        let Condition::Count(p, n) = parse("*3{#[TI]!=1}") else {
+            panic!("not a count");
+        };
+        assert!(matches!(p, Pattern::Any(..)));
+        assert_eq!(n, Compare::Ne(1));
+
+        let Condition::Before(a, _) = parse("*3{[TI]!<O}") else {
+            panic!("not an ordering");
+        };
+        assert!(matches!(a, Pattern::All(..)));
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and() {
+        let Condition::And(a, b) = parse("*3{!T<I&O<S}") else {
+            panic!("not a conjunction");
+        };
+        assert!(matches!(*a, Condition::Not(..)));
+        assert!(matches!(*b, Condition::Before(..)));
+
+        let Condition::Or(a, _) = parse("*3{!T<I&O<S|Z<L}") else {
+            panic!("not a disjunction");
+        };
+        assert!(matches!(*a, Condition::And(..)));
+    }
+
+    #[test]
+    fn not_of_groups_and_nots() {
+        let Condition::Not(c) = parse("*3{!(T<I&O<S)}") else {
+            panic!("not a negation");
+        };
+        assert!(matches!(*c, Condition::Group(..)));
+
+        let Condition::Not(c) = parse("*3{!!T<I}") else {
+            panic!("not a negation");
+        };
+        assert!(matches!(*c, Condition::Not(..)));
+    }
+
+    #[test]
+    fn places() {
+        for (text, places) in [
+            ("*3{@T=-1}", vec![Place::At(-1)]),
+            ("*3{@T=-3..-1}", vec![Place::Range(-3, Some(-1))]),
+            ("*3{@T=1..}", vec![Place::Range(1, None)]),
+            ("*3{@T=-2..}", vec![Place::Range(-2, None)]),
+            ("*3{@T=-3..=-2}", vec![Place::RangeInclusive(-3, -2)]),
+            (
+                "*3{@T=0,2..,-1}",
+                vec![Place::At(0), Place::Range(2, None), Place::At(-1)],
+            ),
+        ] {
+            assert!(
+                matches!(parse(text), Condition::Location(_, x) if x == places),
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn places_count_from_either_end() {
+        let within = |p: Place| (0..4).filter(|&n| p.contains(n, 4)).collect::<Vec<_>>();
+        assert_eq!(within(Place::At(-1)), [3]);
+        assert_eq!(within(Place::Range(-2, None)), [2, 3]);
+        assert_eq!(within(Place::Range(1, Some(-1))), [1, 2]);
+        assert_eq!(within(Place::RangeInclusive(-4, 1)), [0, 1]);
+    }
+
+    #[test]
+    fn numbers_too_large_are_errors() {
+        let big = "99999999999999999999";
+        for text in [
+            format!("*p3{{@T={big}}}"),
+            format!("*p3{{@T=-{big}..}}"),
+            format!("*p3{{#T={big}}}"),
+            format!("*p3{{#T=1..={big}}}"),
+            format!("[TI]{big}"),
+        ] {
+            let e = Pattern::<Bag7>::new(&text).unwrap_err();
+            assert!(e.to_string().contains("too large"), "{text}: {e}");
+        }
+    }
+}
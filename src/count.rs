@@ -0,0 +1,537 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    bag::Bag,
+    condition::{Compare, Condition, Place},
+    pattern::Pattern,
+    queue::Queue,
+};
+
+// expanding `Seq`s of `Either`s multiplies out, so stop analyzing past this many alternatives
+const MAX_ALTERNATIVES: usize = 256;
+
+// A fixed-length piece of a queue: one piece from a set, or `k` distinct draws from a multiset
+#[derive(Clone, Debug)]
+pub enum Segment {
+    Slot(Vec<char>),
+    Draw(Vec<char>, usize),
+}
+
+impl Segment {
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Slot(..) => 1,
+            Self::Draw(_, k) => *k,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// A condition compiled down to per-position state machines over single pieces
+#[derive(Clone, Debug)]
+enum Atom {
+    Before(Vec<char>, Vec<char>),
+    Count(Vec<char>, Compare),
+    Location(Vec<char>, Vec<Place>),
+}
+
+impl Atom {
+    fn initial(&self) -> usize {
+        0
+    }
+
+    fn step(&self, state: usize, piece: char, index: usize, len: usize) -> usize {
+        match self {
+            // 0 = neither seen yet, 1 = `a` came first, 2 = `b` came first (or both at once)
+            Self::Before(a, b) => {
+                if state != 0 {
+                    state
+                } else if b.contains(&piece) {
+                    2
+                } else if a.contains(&piece) {
+                    1
+                } else {
+                    0
+                }
+            }
+            Self::Count(a, n) => {
+                if a.contains(&piece) {
+                    (state + 1).min(n.max() + 1)
+                } else {
+                    state
+                }
+            }
+            Self::Location(a, places) => {
+                if a.contains(&piece) && places.iter().any(|p| p.contains(index, len)) {
+                    1
+                } else {
+                    state
+                }
+            }
+        }
+    }
+
+    fn accepts(&self, state: usize) -> bool {
+        match self {
+            Self::Before(..) | Self::Location(..) => state == 1,
+            Self::Count(_, n) => n.test(state),
+        }
+    }
+
+    // Whether this could still end up accepted, and whether it could still end up rejected,
+    // with `index` of `len` pieces placed so far
+    fn outcomes(&self, state: usize, index: usize, len: usize) -> (bool, bool) {
+        let left = len - index;
+        match self {
+            Self::Before(..) if state != 0 => (state == 1, state != 1),
+            Self::Before(..) => (left > 0, true),
+            Self::Count(_, n) => {
+                let reachable = state..=(state + left).min(n.max() + 1);
+                (
+                    reachable.clone().any(|x| n.test(x)),
+                    reachable.into_iter().any(|x| !n.test(x)),
+                )
+            }
+            Self::Location(..) if state == 1 => (true, false),
+            Self::Location(_, places) => (
+                (index..len).any(|i| places.iter().any(|p| p.contains(i, len))),
+                true,
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Formula {
+    Atom(usize),
+    And(Box<Self>, Box<Self>),
+    Or(Box<Self>, Box<Self>),
+    Not(Box<Self>),
+}
+
+impl Formula {
+    fn eval(&self, atoms: &[Atom], states: &[usize]) -> bool {
+        match self {
+            Self::Atom(i) => atoms[*i].accepts(states[*i]),
+            Self::And(a, b) => a.eval(atoms, states) && b.eval(atoms, states),
+            Self::Or(a, b) => a.eval(atoms, states) || b.eval(atoms, states),
+            Self::Not(a) => !a.eval(atoms, states),
+        }
+    }
+
+    // Whether this could still end up true, and whether it could still end up false
+    fn outcomes(&self, atoms: &[Atom], states: &[usize], index: usize, len: usize) -> (bool, bool) {
+        match self {
+            Self::Atom(i) => atoms[*i].outcomes(states[*i], index, len),
+            Self::And(a, b) => {
+                let (x, y) = (
+                    a.outcomes(atoms, states, index, len),
+                    b.outcomes(atoms, states, index, len),
+                );
+                (x.0 && y.0, x.1 || y.1)
+            }
+            Self::Or(a, b) => {
+                let (x, y) = (
+                    a.outcomes(atoms, states, index, len),
+                    b.outcomes(atoms, states, index, len),
+                );
+                (x.0 || y.0, x.1 && y.1)
+            }
+            Self::Not(a) => {
+                let (t, f) = a.outcomes(atoms, states, index, len);
+                (f, t)
+            }
+        }
+    }
+}
+
+// A condition over single pieces, compiled to run over a queue in one pass
+#[derive(Clone, Debug)]
+pub struct Matcher {
+    atoms: Vec<Atom>,
+    formula: Formula,
+}
+
+impl Matcher {
+    pub fn new<B: Bag>(condition: &Condition<B>) -> Option<Self> {
+        let mut atoms = vec![];
+        let formula = Pattern::compile(condition, &mut atoms)?;
+        Some(Self { atoms, formula })
+    }
+
+    // Agrees with `Condition::has` on the condition this was built from
+    pub fn has(&self, queue: &Queue) -> bool {
+        let mut states = self.start();
+        for (i, &c) in queue.vec().iter().enumerate() {
+            states = self.step(&states, c, i, queue.len());
+        }
+
+        self.accepts(&states)
+    }
+
+    // The state of every atom before any pieces, to be fed through `step` one piece at a time
+    pub fn start(&self) -> Vec<usize> {
+        self.atoms.iter().map(Atom::initial).collect()
+    }
+
+    pub fn step(&self, states: &[usize], piece: char, index: usize, len: usize) -> Vec<usize> {
+        self.atoms
+            .iter()
+            .zip(states)
+            .map(|(a, &s)| a.step(s, piece, index, len))
+            .collect()
+    }
+
+    pub fn accepts(&self, states: &[usize]) -> bool {
+        self.formula.eval(&self.atoms, states)
+    }
+
+    // Whether some way of placing the rest of `len` pieces, `index` of them in, could still be
+    // accepted. It may say yes when there is none, but never no when there is one
+    pub fn viable(&self, states: &[usize], index: usize, len: usize) -> bool {
+        self.formula.outcomes(&self.atoms, states, index, len).0
+    }
+}
+
+impl<B> Pattern<B>
+where
+    B: Bag,
+{
+    // Counts `Condition` and `Unique` patterns from their structure, if it can be analyzed
+    pub fn count_structural(&self) -> Option<usize> {
+        match self {
+            Self::Group(p) => p.count_structural(),
+            Self::Condition(..) => {
+                let mut base = self;
+                let mut conditions = vec![];
+                loop {
+                    match base {
+                        Self::Condition(p, c) => {
+                            conditions.push(&**c);
+                            base = p;
+                        }
+                        Self::Group(p) => base = p,
+                        _ => break,
+                    }
+                }
+
+                let mut atoms = vec![];
+                let mut formula = Self::compile(conditions[0], &mut atoms)?;
+                for c in &conditions[1..] {
+                    formula =
+                        Formula::And(Box::new(formula), Box::new(Self::compile(c, &mut atoms)?));
+                }
+
+                let alternatives = base.alternatives()?;
+                Some(
+                    alternatives
+                        .iter()
+                        .map(|x| Self::count_filtered(x, &atoms, &formula))
+                        .sum(),
+                )
+            }
+            Self::Unique(p) => {
+                let mut seen: HashSet<Vec<char>> = HashSet::new();
+                for segments in p.alternatives()? {
+                    seen.extend(Self::multisets(&segments));
+                }
+
+                Some(seen.len())
+            }
+            _ => Some(self.count()),
+        }
+    }
+
+    // The pieces this pattern matches, if every queue it matches is exactly one piece long
+    pub fn slot(&self) -> Option<Vec<char>> {
+        match self {
+            Self::Single(c) => Some(vec![*c]),
+            Self::Wildcard => Some(B::wildcard()),
+            Self::Except(t) => Some(Self::except(t)),
+            Self::Group(p) => p.slot(),
+            Self::Either(a, b) => Some([a.slot()?, b.slot()?].concat()),
+            Self::Any(t) => t
+                .iter()
+                .map(|x| x.slot())
+                .collect::<Option<Vec<_>>>()
+                .map(|x| x.concat()),
+            _ => None,
+        }
+    }
+
+    // Splits this pattern into alternatives of fixed-length segments, one per way of matching
+    pub fn alternatives(&self) -> Option<Vec<Vec<Segment>>> {
+        if let Some(s) = self.slot() {
+            return Some(vec![vec![Segment::Slot(s)]]);
+        }
+
+        match self {
+            Self::Group(p) => p.alternatives(),
+            Self::Either(a, b) => Some([a.alternatives()?, b.alternatives()?].concat()),
+            Self::Any(t) => Some(
+                t.iter()
+                    .map(|x| x.alternatives())
+                    .collect::<Option<Vec<_>>>()?
+                    .concat(),
+            ),
+            Self::Seq(a, b) => {
+                let l = a.alternatives()?;
+                let r = b.alternatives()?;
+                if l.len() * r.len() > MAX_ALTERNATIVES {
+                    return None;
+                }
+
+                let mut v = vec![];
+                for x in &l {
+                    for y in &r {
+                        v.push([x.clone(), y.clone()].concat());
+                    }
+                }
+
+                Some(v)
+            }
+            Self::Take(c, n) => Some(vec![vec![Segment::Draw(c.slot()?, *n)]]),
+            Self::All(c) => {
+                let s = c.slot()?;
+                let l = s.len();
+                Some(vec![vec![Segment::Draw(s, l)]])
+            }
+            _ => None,
+        }
+    }
+
+    fn compile(condition: &Condition<B>, atoms: &mut Vec<Atom>) -> Option<Formula> {
+        let atom = match condition {
+            Condition::Group(c) => return Self::compile(c, atoms),
+            Condition::And(a, b) => {
+                return Some(Formula::And(
+                    Box::new(Self::compile(a, atoms)?),
+                    Box::new(Self::compile(b, atoms)?),
+                ));
+            }
+            Condition::Or(a, b) => {
+                return Some(Formula::Or(
+                    Box::new(Self::compile(a, atoms)?),
+                    Box::new(Self::compile(b, atoms)?),
+                ));
+            }
+            Condition::Not(a) => return Some(Formula::Not(Box::new(Self::compile(a, atoms)?))),
+            Condition::Before(a, b) => Atom::Before(a.slot()?, b.slot()?),
+            Condition::After(a, b) => Atom::Before(b.slot()?, a.slot()?),
+            Condition::Count(a, n) => Atom::Count(a.slot()?, *n),
+            Condition::Location(a, n) => Atom::Location(a.slot()?, n.clone()),
+            Condition::Phantom(..) => return None,
+        };
+
+        atoms.push(atom);
+        Some(Formula::Atom(atoms.len() - 1))
+    }
+
+    // Dynamic programming over positions, tracking the state of every atom
+    fn count_filtered(segments: &[Segment], atoms: &[Atom], formula: &Formula) -> usize {
+        let mut states: HashMap<Vec<usize>, usize> = HashMap::new();
+        states.insert(atoms.iter().map(Atom::initial).collect(), 1);
+        let mut index = 0;
+        let len = segments.iter().map(Segment::len).sum();
+
+        let step = |s: &[usize], piece: char, index: usize| -> Vec<usize> {
+            atoms
+                .iter()
+                .zip(s)
+                .map(|(a, &x)| a.step(x, piece, index, len))
+                .collect()
+        };
+
+        for segment in segments {
+            match segment {
+                Segment::Slot(pieces) => {
+                    let mut next = HashMap::new();
+                    for (s, n) in &states {
+                        for &p in pieces {
+                            *next.entry(step(s, p, index)).or_insert(0) += n;
+                        }
+                    }
+
+                    states = next;
+                    index += 1;
+                }
+                Segment::Draw(pieces, k) => {
+                    let (distinct, counts) = Self::tally(pieces);
+                    let mut inner: HashMap<(Vec<usize>, Vec<usize>), usize> = HashMap::new();
+                    for (s, n) in states {
+                        *inner.entry((s, counts.clone())).or_insert(0) += n;
+                    }
+
+                    for _ in 0..*k {
+                        let mut next = HashMap::new();
+                        for ((s, left), n) in &inner {
+                            for (j, &p) in distinct.iter().enumerate() {
+                                if left[j] == 0 {
+                                    continue;
+                                }
+
+                                let mut l = left.clone();
+                                l[j] -= 1;
+                                *next.entry((step(s, p, index), l)).or_insert(0) += n;
+                            }
+                        }
+
+                        inner = next;
+                        index += 1;
+                    }
+
+                    states = HashMap::new();
+                    for ((s, _), n) in inner {
+                        *states.entry(s).or_insert(0) += n;
+                    }
+                }
+            }
+        }
+
+        states
+            .iter()
+            .filter(|(s, _)| formula.eval(atoms, s))
+            .map(|(_, n)| n)
+            .sum()
+    }
+
+    // Every distinct multiset of pieces (as a sorted list) that a segment sequence can produce
+    fn multisets(segments: &[Segment]) -> HashSet<Vec<char>> {
+        let mut current: HashSet<Vec<char>> = HashSet::from([vec![]]);
+        for segment in segments {
+            let choices: Vec<Vec<char>> = match segment {
+                Segment::Slot(pieces) => pieces.iter().map(|&p| vec![p]).collect(),
+                Segment::Draw(pieces, k) => {
+                    let (distinct, counts) = Self::tally(pieces);
+                    let mut v = vec![];
+                    Self::submultisets(&distinct, &counts, *k, &mut vec![], &mut v);
+                    v
+                }
+            };
+
+            let mut next = HashSet::new();
+            for m in &current {
+                for c in &choices {
+                    let mut x = [m.clone(), c.clone()].concat();
+                    x.sort_unstable();
+                    next.insert(x);
+                }
+            }
+
+            current = next;
+        }
+
+        current
+    }
+
+    fn submultisets(
+        distinct: &[char],
+        counts: &[usize],
+        k: usize,
+        prefix: &mut Vec<char>,
+        out: &mut Vec<Vec<char>>,
+    ) {
+        if k == 0 {
+            out.push(prefix.clone());
+            return;
+        }
+
+        let Some((&p, rest)) = distinct.split_first() else {
+            return;
+        };
+
+        for take in 0..=counts[0].min(k) {
+            prefix.extend(std::iter::repeat_n(p, take));
+            Self::submultisets(rest, &counts[1..], k - take, prefix, out);
+            prefix.truncate(prefix.len() - take);
+        }
+    }
+
+    fn tally(pieces: &[char]) -> (Vec<char>, Vec<usize>) {
+        let mut distinct = vec![];
+        let mut counts = vec![];
+        for &p in pieces {
+            match distinct.iter().position(|&x| x == p) {
+                Some(i) => counts[i] += 1,
+                None => {
+                    distinct.push(p);
+                    counts.push(1);
+                }
+            }
+        }
+
+        (distinct, counts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{bag::Bag7, pattern::Pattern};
+
+    // the structural count of `text`, and the count from expanding its base and filtering
+    fn both(text: &str) -> (Option<usize>, usize) {
+        let p = Pattern::<Bag7>::new(text).unwrap();
+        let Pattern::Condition(base, condition) = &p else {
+            panic!("{text} is not a condition");
+        };
+        let filtered = base.iter_queues().filter(|q| condition.has(q)).count();
+        (p.count_structural(), filtered)
+    }
+
+    fn check(texts: &[&str]) {
+        for text in texts {
+            let (counted, filtered) = both(text);
+            assert_eq!(counted, Some(filtered), "{text}");
+        }
+    }
+
+    #[test]
+    fn takes() {
+        check(&[
+            "*p4{T<I}",
+            "[TIOS]p3{#[TI]=1}",
+            "*p3{@O=-1}",
+            "[TIOSZ]p2{!S<Z}",
+        ]);
+    }
+
+    #[test]
+    fn alls() {
+        check(&["*!{T<I&I<O}", "[TIOS]!{@T=1..=2}", "[TIOS]!{#[TI]>=1|@S=0}"]);
+    }
+
+    #[test]
+    fn excepts() {
+        check(&["[^TI]p3{L<J}", "([^TI][^OS]){#Z=1}", "[^T]!{@Z=0,-1}"]);
+    }
+
+    #[test]
+    fn sequences_and_choices() {
+        check(&[
+            "(T*p3){#T=1}",
+            "(*p2;[TI]!){@I=1}",
+            "(*p3[^TI]p2){J<L&#O<=1}",
+            "(*p2*p2){@T=-2..}",
+        ]);
+    }
+
+    #[test]
+    fn stacked_conditions() {
+        check(&["(*p4{T<I}){@O=0}", "([TIOS]!{#T=1}){S<Z|!I<O}"]);
+    }
+
+    #[test]
+    fn unique() {
+        for text in ["*p3?", "([TI]*;*[TI])?", "[TIO]!?"] {
+            let p = Pattern::<Bag7>::new(text).unwrap();
+            assert_eq!(
+                p.count_structural(),
+                Some(p.iter_queues().count()),
+                "{text}"
+            );
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::{collections::HashMap, fmt::Display};
+
+use crate::{bag::Bag, pattern::Pattern, queue::Queue};
+
+// A queue matched more often by one pattern than another
+#[derive(Clone, Debug, PartialEq)]
+pub struct Witness {
+    pub queue: Queue,
+    pub here: usize,
+    pub there: usize,
+}
+
+// Why two patterns aren't equivalent: the shortest queue each has over the other, if any
+#[derive(Clone, Debug, PartialEq)]
+pub struct Difference {
+    pub multiset: bool,
+    pub left: Option<Witness>,
+    pub right: Option<Witness>,
+}
+
+impl<B> Pattern<B>
+where
+    B: Bag,
+{
+    pub fn equivalent(&self, other: &Self) -> bool {
+        self.difference(other, false).is_none()
+    }
+
+    // How this pattern and `other` differ in the queues they match, if they do. With `multiset`,
+    // matching a queue more times than the other counts as a difference too
+    pub fn difference(&self, other: &Self, multiset: bool) -> Option<Difference> {
+        let left = Self::tally_queues(self, multiset);
+        let right = Self::tally_queues(other, multiset);
+        let witness = |a: &HashMap<Queue, usize>, b: &HashMap<Queue, usize>| {
+            a.iter()
+                .filter(|(q, n)| **n > b.get(*q).copied().unwrap_or(0))
+                .min_by(|x, y| (x.0.len(), x.0).cmp(&(y.0.len(), y.0)))
+                .map(|(q, n)| Witness {
+                    queue: q.clone(),
+                    here: *n,
+                    there: b.get(q).copied().unwrap_or(0),
+                })
+        };
+
+        let d = Difference {
+            multiset,
+            left: witness(&left, &right),
+            right: witness(&right, &left),
+        };
+
+        (d.left.is_some() || d.right.is_some()).then_some(d)
+    }
+
+    // How many times each queue is matched, or just whether it is
+    fn tally_queues(&self, multiset: bool) -> HashMap<Queue, usize> {
+        let mut map = HashMap::new();
+        for q in self.iter_queues() {
+            let n = map.entry(q).or_insert(0);
+            *n = if multiset { *n + 1 } else { 1 };
+        }
+
+        map
+    }
+}
+
+impl Display for Difference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let side = |w: &Option<Witness>| match w {
+            Some(w) if self.multiset => format!("{:?} ({} vs {})", w.queue, w.here, w.there),
+            Some(w) => format!("{:?}", w.queue),
+            None => "none".to_string(),
+        };
+
+        let (a, b) = if self.multiset {
+            ("more often in a", "more often in b")
+        } else {
+            ("only in a", "only in b")
+        };
+
+        writeln!(f, "not equivalent")?;
+        writeln!(f, "{a}: {}", side(&self.left))?;
+        write!(f, "{b}: {}", side(&self.right))
+    }
+}
@@ -0,0 +1,174 @@
+use std::{fmt::Display, ops::Range};
+
+use chumsky::error::{Rich, RichPattern, RichReason};
+
+use crate::queue::Queue;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParseError {
+    Syntax {
+        file: Option<String>,
+        source: String,
+        diagnostics: Vec<Diagnostic>,
+    },
+    Io {
+        file: String,
+        reason: String,
+    },
+}
+
+// A single parser complaint, located by byte span within the source
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub span: Range<usize>,
+    pub found: Option<char>,
+    pub expected: Vec<String>,
+    pub reason: Option<String>,
+}
+
+// A queue holding something that isn't a piece of the active bag
+#[derive(Clone, Debug, PartialEq)]
+pub struct InvalidPiece {
+    pub queue: Queue,
+    pub index: usize,
+    pub piece: char,
+}
+
+impl ParseError {
+    pub fn new<'a>(source: &str, errors: impl IntoIterator<Item = Rich<'a, char>>) -> Self {
+        Self::from_diagnostics(source, errors.into_iter().map(Diagnostic::from).collect())
+    }
+
+    pub fn from_diagnostics(source: &str, diagnostics: Vec<Diagnostic>) -> Self {
+        Self::Syntax {
+            file: None,
+            source: source.to_string(),
+            diagnostics,
+        }
+    }
+
+    pub fn in_file(self, name: &str) -> Self {
+        match self {
+            Self::Syntax {
+                source,
+                diagnostics,
+                ..
+            } => Self::Syntax {
+                file: Some(name.to_string()),
+                source,
+                diagnostics,
+            },
+            x => x,
+        }
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        match self {
+            Self::Syntax { diagnostics, .. } => diagnostics,
+            Self::Io { .. } => &[],
+        }
+    }
+}
+
+impl Diagnostic {
+    pub fn message(&self) -> String {
+        if let Some(r) = &self.reason {
+            return r.clone();
+        }
+
+        let found = match self.found {
+            Some(c) => format!("{c:?}"),
+            None => "end of input".to_string(),
+        };
+
+        match self.expected.as_slice() {
+            [] => format!("found {found}"),
+            [x] => format!("found {found}, expected {x}"),
+            [xs @ .., x] => format!("found {found}, expected {} or {x}", xs.join(", ")),
+        }
+    }
+
+    // 1-based line and column (in chars) of the start of the span
+    pub fn location(&self, source: &str) -> (usize, usize) {
+        let before = &source[..self.span.start.min(source.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+        (line, column)
+    }
+
+    pub fn render(&self, file: Option<&str>, source: &str) -> String {
+        let (line, column) = self.location(source);
+        let text = source.lines().nth(line - 1).unwrap_or("");
+        let width = source[self.span.start.min(source.len())..self.span.end.min(source.len())]
+            .chars()
+            .take_while(|&c| c != '\n')
+            .count()
+            .max(1);
+        let gutter = " ".repeat(line.to_string().len());
+
+        format!(
+            "{}\n{gutter}--> {}:{line}:{column}\n{gutter} |\n{line} | {text}\n{gutter} | {}{}",
+            self.message(),
+            file.unwrap_or("<pattern>"),
+            " ".repeat(column - 1),
+            "^".repeat(width),
+        )
+    }
+}
+
+impl From<Rich<'_, char>> for Diagnostic {
+    fn from(e: Rich<'_, char>) -> Self {
+        let reason = match e.reason() {
+            RichReason::Custom(s) => Some(s.clone()),
+            _ => None,
+        };
+
+        Self {
+            span: e.span().into_range(),
+            found: e.found().copied(),
+            expected: e
+                .expected()
+                .map(|x| match x {
+                    RichPattern::Token(t) => format!("{:?}", **t),
+                    x => x.to_string(),
+                })
+                .collect(),
+            reason,
+        }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Syntax {
+                file,
+                source,
+                diagnostics,
+            } => write!(
+                f,
+                "{}",
+                diagnostics
+                    .iter()
+                    .map(|x| x.render(file.as_deref(), source))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            ),
+            Self::Io { file, reason } => write!(f, "could not read {file}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl Display for InvalidPiece {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?} at position {} of queue {:?} is not a piece",
+            self.piece, self.index, self.queue
+        )
+    }
+}
+
+impl std::error::Error for InvalidPiece {}
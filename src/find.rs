@@ -1,38 +1,73 @@
+use std::cell::Cell;
 use std::collections::HashSet;
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+
+use itertools::Itertools;
+
+use crate::approx::Tolerance;
 use crate::bag::Bag;
-use crate::pattern::Pattern;
+use crate::condition::Condition;
+use crate::parallel;
+use crate::pattern::{Optimization, Pattern};
 use crate::queue::Queue;
+use crate::simplify::Settings;
 
 impl<B> Pattern<B>
 where
     B: Bag,
 {
-    pub fn find_exhaustive(universe: &[Queue], set: &[Queue]) -> Option<Self> {
-        // early return if set is empty or all queues are in set
+    // The literal `A;B;C;...` of the set, which always matches it exactly
+    pub fn find_literal(set: &[Queue]) -> Option<Self> {
         if set.is_empty() {
             return None;
         }
 
-        // sanity check
-        let set_queues: HashSet<&Queue> = set.iter().collect();
-        let universe_queues: HashSet<&Queue> = universe.iter().collect();
+        Some(Self::create_literal_pattern(set))
+    }
 
-        if !set_queues.is_subset(&universe_queues) {
-            println!("no sanity");
-            return None;
+    pub fn find_exhaustive(
+        universe: &[Queue],
+        set: &[Queue],
+        opt_level: Optimization,
+        tolerance: Tolerance,
+        budget: Budget,
+    ) -> (Option<Self>, Stats) {
+        // early return if set is empty or all queues are in set
+        if set.is_empty() {
+            return (None, Stats::default());
         }
 
-        // turn set into A;B;C;D;...
-        let initial_pattern = Self::create_literal_pattern(set);
+        // set queues the universe leaves out are taken to be in it, which `Search::new` sees to
 
-        // sanity check
-        if !initial_pattern.check(universe, set) {
-            println!("no sanity");
-            return None;
-        }
+        let deadline = budget.timeout.map(|x| Instant::now() + x);
+        let (found, mut stats) =
+            Search::<B>::new(universe, set, opt_level, tolerance, budget).run();
+        let stopped = stats.stopped;
+        let pattern = found.unwrap_or_else(|| {
+            // nothing small enough turned up, so fall back to rewriting the literal, if there is
+            // any budget left, for as long as it and the level allow, and without printing every
+            // rewrite into the output
+            let initial_pattern = Self::create_literal_pattern(set);
+            if stopped {
+                return initial_pattern;
+            }
 
-        // simplify forever
-        Some(initial_pattern.simplify())
+            let attempts = Cell::new(opt_level.fallback());
+            let simplified = initial_pattern.simplify_with(Settings {
+                deadline,
+                attempts: Some(&attempts),
+                ..Settings::default()
+            });
+            if simplified.check(universe, set) {
+                simplified
+            } else {
+                initial_pattern
+            }
+        });
+
+        stats.best_size = Some(pattern.size());
+        (Some(pattern), stats)
     }
 
     fn create_literal_pattern(queues: &[Queue]) -> Self {
@@ -77,5 +112,500 @@ where
     fn queue_to_chars(queue: &Queue) -> Vec<char> {
         queue.vec().clone()
     }
+
+    // Wraps a pattern in a group when it would otherwise print ambiguously as an operand
+    fn operand(self, atomic: bool) -> Self {
+        match self {
+            Self::Single(..)
+            | Self::Wildcard
+            | Self::Any(..)
+            | Self::Except(..)
+            | Self::Group(..) => self,
+            Self::Either(..) => Self::Group(Box::new(self)),
+            x if atomic => Self::Group(Box::new(x)),
+            x => x,
+        }
+    }
+}
+
+// conditions are only looked for on the first few bases of each size, and only on bases
+// small enough to run every candidate atom over
+const MAX_CONDITION_BASES: usize = 4;
+const MAX_CONDITION_QUEUES: usize = 20_000;
+
+// the bank keeps every queue of every candidate, so stop growing it before it eats all memory
+const MAX_BANK_QUEUES: usize = 20_000_000;
+
+// candidates handed out to the worker threads at a time when pairing them up
+const PAIR_BATCH: usize = 64;
+
+// Limits on a search, on top of those of its `Optimization`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Budget {
+    pub timeout: Option<Duration>,
+    pub max_candidates: Option<usize>,
+}
+
+// How a search went
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Stats {
+    // candidate patterns built
+    pub tried: usize,
+    // candidates thrown away for matching nothing new
+    pub pruned: usize,
+    // size of the pattern that was settled on
+    pub best_size: Option<usize>,
+    // whether the search ran out of budget before it was done
+    pub stopped: bool,
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "tried {} candidates, pruned {}", self.tried, self.pruned)?;
+        match self.best_size {
+            Some(n) => write!(f, ", best size {n}"),
+            None => write!(f, ", nothing found"),
+        }
+    }
+}
+
+// A pattern found during search, along with the sorted, deduplicated queues it matches
+#[derive(Clone)]
+struct Candidate<B: Bag> {
+    pattern: Pattern<B>,
+    queues: Vec<Queue>,
+}
+
+// Bottom-up enumeration of patterns by increasing `Pattern::size`, keeping only the smallest
+// pattern for each distinct set of matched queues
+pub struct Search<B: Bag> {
+    target: Vec<Queue>,
+    target_set: HashSet<Queue>,
+    universe: HashSet<Queue>,
+    // every contiguous piece of a universe queue. matches outside of these can never end up
+    // inside the universe, so candidates only keep (and are compared by) the ones in here
+    substrings: HashSet<Queue>,
+    // the length of the longest universe queue
+    longest: usize,
+    bank: Vec<Vec<Candidate<B>>>,
+    pending: Vec<Vec<Candidate<B>>>,
+    seen: HashSet<Vec<Queue>>,
+    tolerance: Tolerance,
+    max_size: usize,
+    max_candidates: usize,
+    deadline: Option<Instant>,
+    tried: usize,
+    pruned: usize,
+    stored: usize,
+    // the smallest conditioned pattern found so far, with its size
+    conditioned: Option<(usize, Pattern<B>)>,
+}
+
+impl<B: Bag> Search<B> {
+    pub fn new(
+        universe: &[Queue],
+        set: &[Queue],
+        opt_level: Optimization,
+        tolerance: Tolerance,
+        budget: Budget,
+    ) -> Self {
+        let mut target = set.to_vec();
+        target.sort();
+        target.dedup();
+
+        let universe: HashSet<Queue> = universe.iter().chain(set).cloned().collect();
+        let mut substrings = HashSet::new();
+        for q in &universe {
+            for start in 0..q.len() {
+                for end in start + 1..=q.len() {
+                    substrings.insert(q.slice(start, end));
+                }
+            }
+        }
+
+        let longest = universe.iter().map(Queue::len).max().unwrap_or(0);
+        let (max_size, max_candidates) = opt_level.effort();
+        Self {
+            target_set: target.iter().cloned().collect(),
+            target,
+            universe,
+            substrings,
+            longest,
+            bank: vec![vec![]],
+            pending: vec![],
+            seen: HashSet::new(),
+            tolerance,
+            max_size,
+            max_candidates: budget.max_candidates.unwrap_or(max_candidates),
+            deadline: budget.timeout.map(|x| Instant::now() + x),
+            tried: 0,
+            pruned: 0,
+            stored: 0,
+            conditioned: None,
+        }
+    }
+
+    pub fn run(mut self) -> (Option<Pattern<B>>, Stats) {
+        let mut found = None;
+        for size in 1..=self.max_size {
+            if let Some((s, _)) = &self.conditioned
+                && *s <= size
+            {
+                break;
+            }
+
+            self.generate(size);
+            found = self.finalize(size);
+            if found.is_some() {
+                break;
+            }
+
+            self.try_conditions(size);
+            if self.exhausted() {
+                break;
+            }
+        }
+
+        // the budget only ever gets used up further, so if it ran out at any point it still is
+        let stats = Stats {
+            tried: self.tried,
+            pruned: self.pruned,
+            best_size: None,
+            stopped: self.exhausted(),
+        };
+
+        (found.or(self.conditioned.map(|(_, p)| p)), stats)
+    }
+
+    // Filters each new pattern that matches all of the target (and then some) with the smallest
+    // condition that leaves exactly the target within the universe
+    fn try_conditions(&mut self, size: usize) {
+        let mut tried = 0;
+        for c in &self.bank[size] {
+            let limit = self
+                .conditioned
+                .as_ref()
+                .map_or(self.max_size + 1, |(s, _)| *s);
+            // `A{...}` costs one more than `A`, plus a group if `A` needs one, plus the condition
+            if tried >= MAX_CONDITION_BASES || size + 3 >= limit || self.exhausted() {
+                return;
+            }
+
+            let queues: Vec<Queue> = c
+                .queues
+                .iter()
+                .filter(|x| self.universe.contains(*x))
+                .cloned()
+                .collect();
+            // a condition can only narrow its base down, so whatever the base misses stays missed
+            let missing = self
+                .target
+                .iter()
+                .filter(|x| queues.binary_search(x).is_err())
+                .count();
+            if queues.len() > MAX_CONDITION_QUEUES || missing > self.tolerance.false_negatives {
+                continue;
+            }
+
+            tried += 1;
+            // conditions bind tighter than sequences, but not tighter than takes
+            let base = match &c.pattern {
+                Pattern::Take(..) | Pattern::All(..) => c.pattern.clone(),
+                x => x.clone().operand(true),
+            };
+            let budget = limit - 2 - base.size();
+            let tolerance = Tolerance {
+                false_negatives: self.tolerance.false_negatives - missing,
+                ..self.tolerance
+            };
+            if let Some(cond) =
+                Condition::find(&queues, &self.target_set, budget, tolerance, self.deadline)
+            {
+                let p = Pattern::Condition(Box::new(base), Box::new(cond));
+                self.conditioned = Some((p.size(), p));
+            }
+        }
+    }
+
+    fn exhausted(&self) -> bool {
+        self.tried >= self.max_candidates
+            || self.stored >= MAX_BANK_QUEUES
+            || self.deadline.is_some_and(|x| Instant::now() >= x)
+    }
+
+    // Banks the new candidates of this size, returning the one with the fewest misclassified
+    // queues if any is within tolerance
+    fn finalize(&mut self, size: usize) -> Option<Pattern<B>> {
+        let mut bucket = vec![];
+        let mut best: Option<(usize, Pattern<B>)> = None;
+        if let Some(p) = self.pending.get_mut(size) {
+            for c in std::mem::take(p) {
+                if self.seen.insert(c.queues.clone()) {
+                    let (fp, fn_) = self.errors(&c.queues);
+                    if self.tolerance.allows(fp, fn_)
+                        && best.as_ref().is_none_or(|(e, _)| fp + fn_ < *e)
+                    {
+                        if fp + fn_ == 0 {
+                            return Some(c.pattern);
+                        }
+
+                        best = Some((fp + fn_, c.pattern.clone()));
+                    }
+
+                    self.stored += c.queues.len();
+                    bucket.push(c);
+                } else {
+                    self.pruned += 1;
+                }
+            }
+        }
+
+        self.bank.push(bucket);
+        best.map(|(_, p)| p)
+    }
+
+    // False positives and false negatives of a candidate's queues within the universe
+    fn errors(&self, queues: &[Queue]) -> (usize, usize) {
+        let mut hits = 0;
+        let mut fp = 0;
+        for q in queues.iter().filter(|x| self.universe.contains(*x)) {
+            if self.target_set.contains(q) {
+                hits += 1;
+            } else {
+                fp += 1;
+            }
+        }
+
+        (fp, self.target.len() - hits)
+    }
+
+    fn push(&mut self, found: Vec<(Pattern<B>, Vec<Queue>)>) {
+        for (pattern, queues) in found {
+            let size = pattern.size();
+            if self.pending.len() <= size {
+                self.pending.resize_with(size + 1, Vec::new);
+            }
+
+            self.pending[size].push(Candidate { pattern, queues });
+        }
+    }
+
+    // The canonical queue list of a new candidate, or `None` if it can't be useful
+    fn admit(&self, mut queues: Vec<Queue>) -> Option<Vec<Queue>> {
+        queues.retain(|x| self.substrings.contains(x));
+        if queues.is_empty() {
+            return None;
+        }
+
+        queues.sort();
+        queues.dedup();
+        (!self.seen.contains(&queues)).then_some(queues)
+    }
+
+    // Every sequence and alternative of `a` (of size `i`) with a candidate of size `j`, and how
+    // many of them got thrown away
+    fn pairs(&self, a: &Candidate<B>, i: usize, j: usize) -> (Vec<Candidate<B>>, usize) {
+        let mut found = vec![];
+        let mut pruned = 0;
+        let mut offer = |p: Pattern<B>, q: Vec<Queue>| match self.admit(q) {
+            Some(queues) => found.push(Candidate { pattern: p, queues }),
+            None => pruned += 1,
+        };
+
+        for b in &self.bank[j] {
+            if !matches!(b.pattern, Pattern::Seq(..))
+                && a.queues.len() * b.queues.len() <= self.substrings.len()
+            {
+                let q = a
+                    .queues
+                    .iter()
+                    .flat_map(|x| b.queues.iter().map(|y| x.join(y.clone())))
+                    .collect();
+                let p = Pattern::Seq(
+                    Box::new(a.pattern.clone().operand(false)),
+                    Box::new(b.pattern.clone().operand(false)),
+                );
+                offer(p, q);
+            }
+
+            // `A;B` and `B;A` match the same queues, so only build one of them
+            let left = matches!(a.pattern, Pattern::Either(..));
+            if !matches!(b.pattern, Pattern::Either(..))
+                && (left || i < j || (i == j && a.queues < b.queues))
+            {
+                let q = [a.queues.clone(), b.queues.clone()].concat();
+                let p = Pattern::Either(Box::new(a.pattern.clone()), Box::new(b.pattern.clone()));
+                offer(p, q);
+            }
+        }
+
+        (found, pruned)
+    }
+
+    // Whether a take of single pieces, whose queues are all `len` long, is worth expanding: some
+    // universe queue has to be that long, and its queues, counted without building them, have
+    // to fit in the bank next to the `held` ones
+    fn fits(&self, take: &Pattern<B>, len: usize, held: usize) -> bool {
+        len <= self.longest && self.stored + held + take.count() <= MAX_BANK_QUEUES
+    }
+
+    fn generate(&mut self, size: usize) {
+        let pieces = B::wildcard();
+        let mut found = vec![];
+        // queues held by `found`, which count towards the bank's limit too
+        let held = Cell::new(0);
+        let pruned = Cell::new(0);
+        let mut offer = |this: &Self, p: Pattern<B>, q: Vec<Queue>| match this.admit(q) {
+            Some(q) => {
+                held.set(held.get() + q.len());
+                found.push((p, q));
+            }
+            None => pruned.set(pruned.get() + 1),
+        };
+
+        if size == 1 {
+            for &c in &pieces {
+                offer(self, Pattern::Single(c), vec![Queue::new(vec![c])]);
+            }
+
+            offer(self, Pattern::Wildcard, Pattern::<B>::Wildcard.queues());
+        }
+
+        // piece sets: `[TI]` and `[^TI]` both have size 1 + members
+        if size >= 2 {
+            for set in pieces.iter().copied().combinations(size - 1) {
+                let members: Vec<_> = set.into_iter().map(Pattern::Single).collect();
+                if members.len() > 1 {
+                    let p = Pattern::Any(members.clone());
+                    let q = p.queues();
+                    offer(self, p, q);
+                }
+
+                let p = Pattern::Except(members);
+                let q = p.queues();
+                offer(self, p, q);
+            }
+        }
+
+        // takes and permutations of piece sets
+        if size >= 2 {
+            for a in &self.bank[size - 1] {
+                if self.exhausted() {
+                    break;
+                }
+
+                if a.queues.len() < 2 || a.queues.iter().any(|q| q.len() != 1) {
+                    continue;
+                }
+
+                // a take can draw each piece as many times as the set holds it, and drawing all of
+                // them is written `!`
+                let drawn = a.pattern.count();
+                for n in 2..=drawn {
+                    let set = Box::new(a.pattern.clone().operand(true));
+                    let p = if n == drawn {
+                        Pattern::All(set)
+                    } else {
+                        Pattern::Take(set, n)
+                    };
+                    if self.fits(&p, n, held.get()) {
+                        let q = p.queues();
+                        offer(self, p, q);
+                    } else {
+                        pruned.set(pruned.get() + 1);
+                    }
+                }
+            }
+        }
+
+        // sequences and alternatives, kept left-associative like the parser builds them
+        'pairs: for i in 1..size.saturating_sub(1) {
+            let j = size - 1 - i;
+            for chunk in self.bank[i].chunks(PAIR_BATCH) {
+                if self.exhausted() || self.stored + held.get() >= MAX_BANK_QUEUES {
+                    break 'pairs;
+                }
+
+                self.tried += chunk.len() * self.bank[j].len();
+                for (f, p) in parallel::map(chunk, |a| self.pairs(a, i, j)) {
+                    held.set(held.get() + f.iter().map(|x| x.queues.len()).sum::<usize>());
+                    pruned.set(pruned.get() + p);
+                    found.extend(f.into_iter().map(|x| (x.pattern, x.queues)));
+                }
+            }
+        }
+
+        self.pruned += pruned.get();
+        self.push(found);
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::{Budget, Search};
+    use crate::{
+        approx::Tolerance,
+        bag::Bag7,
+        pattern::{Optimization, Pattern},
+        queue::Queue,
+    };
+
+    fn queues(text: &str) -> Vec<Queue> {
+        Pattern::<Bag7>::new(text).unwrap().queues()
+    }
+
+    // searches for `set` within `universe`, checking that what turns up matches it there
+    fn search(universe: &str, set: &str) -> Pattern<Bag7> {
+        let (universe, set) = (queues(universe), queues(set));
+        let (found, stats) = Search::<Bag7>::new(
+            &universe,
+            &set,
+            Optimization::Quick,
+            Tolerance::default(),
+            Budget::default(),
+        )
+        .run();
+        let found = found.expect("a pattern");
+        assert!(found.check(&universe, &set), "{found}");
+        assert!(!stats.stopped);
+        found
+    }
+
+    #[test]
+    fn finds_patterns_that_check() {
+        for (universe, set) in [
+            ("*p2", "T*;I*"),
+            ("*p2", "[TIO]p2"),
+            ("[TIO]!", "TIO;TOI"),
+            ("[TIOS]p3", "[TI]!O;[TI]!S"),
+        ] {
+            search(universe, set);
+        }
+    }
+
+    #[test]
+    fn smallest_pattern_wins() {
+        for (universe, set, size) in [
+            ("*p1", "T", 1),
+            ("*p2", "T*", 3),
+            ("*p2", "[TI]p2", 4),
+            ("*p2", "T*;I*", 5),
+        ] {
+            let found = search(universe, set);
+            assert_eq!(found.size(), size, "{found}");
+        }
+    }
+
+    #[test]
+    fn set_queues_outside_the_universe_count_as_in_it() {
+        let found = search("*p2", "TT;TI");
+        assert_eq!(found, Pattern::new("T[TI]").unwrap());
+    }
+
+    #[test]
+    fn queues_outside_the_universe_are_dont_cares() {
+        // `T*` also matches `TT`, which `*p2` leaves out
+        let found = search("*p2", "TI;TL;TJ;TO;TS;TZ");
+        assert_eq!(found, Pattern::new("T*").unwrap());
+    }
+}
@@ -0,0 +1,219 @@
+use std::{collections::HashSet, time::Instant};
+
+use itertools::Itertools;
+
+use crate::{
+    approx::Tolerance,
+    bag::Bag,
+    condition::{Compare, Condition, Place},
+    count::Matcher,
+    parallel,
+    pattern::Pattern,
+    queue::Queue,
+};
+
+// how many pairs of atoms to try between looking at the clock
+const DEADLINE_CHECKS: usize = 1024;
+
+// Which of a fixed list of queues something holds for, one bit per queue
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Bits(Vec<u64>);
+
+impl Bits {
+    fn new(values: impl Iterator<Item = bool>) -> Self {
+        let mut v = vec![];
+        for (i, b) in values.enumerate() {
+            if i.is_multiple_of(64) {
+                v.push(0);
+            }
+
+            if b {
+                v[i / 64] |= 1 << (i % 64);
+            }
+        }
+
+        Self(v)
+    }
+
+    fn not(&self, len: usize) -> Self {
+        let mut v: Vec<u64> = self.0.iter().map(|x| !x).collect();
+        if !len.is_multiple_of(64)
+            && let Some(last) = v.last_mut()
+        {
+            *last &= (1 << (len % 64)) - 1;
+        }
+
+        Self(v)
+    }
+
+    fn and(&self, rhs: &Self) -> Self {
+        Self(self.0.iter().zip(&rhs.0).map(|(a, b)| a & b).collect())
+    }
+
+    fn or(&self, rhs: &Self) -> Self {
+        Self(self.0.iter().zip(&rhs.0).map(|(a, b)| a | b).collect())
+    }
+
+    // How many are set here but not in `rhs`
+    fn excess(&self, rhs: &Self) -> usize {
+        self.0
+            .iter()
+            .zip(&rhs.0)
+            .map(|(a, b)| (a & !b).count_ones() as usize)
+            .sum()
+    }
+}
+
+impl<B> Condition<B>
+where
+    B: Bag,
+{
+    // Looks for the smallest condition (no bigger than `max_size`) that holds for the queues of
+    // `queues` that are in `target`, getting at most `tolerance` of them wrong
+    pub fn find(
+        queues: &[Queue],
+        target: &HashSet<Queue>,
+        max_size: usize,
+        tolerance: Tolerance,
+        deadline: Option<Instant>,
+    ) -> Option<Self> {
+        let len = queues.len();
+        let want = Bits::new(queues.iter().map(|x| target.contains(x)));
+        let errors = |b: &Bits| (b.excess(&want), want.excess(b));
+        let fits = |b: &Bits| {
+            let (fp, fn_) = errors(b);
+            tolerance.allows(fp, fn_)
+        };
+
+        // every atom that says something different, keeping the smallest way to say it
+        let mut atoms: Vec<(Self, Bits)> = vec![];
+        let mut seen = HashSet::new();
+        let mut candidates = Self::atoms(queues.iter().map(Queue::len).max().unwrap_or(0));
+        candidates.sort_by_key(|x| x.size());
+        let evaluated = parallel::map(&candidates, |c| match Matcher::new(c) {
+            Some(m) => Bits::new(queues.iter().map(|x| m.has(x))),
+            None => Bits::new(queues.iter().map(|x| c.has(x))),
+        });
+        for (c, bits) in candidates.into_iter().zip(evaluated) {
+            if seen.insert(bits.clone()) {
+                atoms.push((c, bits));
+            }
+        }
+
+        let negated: Vec<(Self, Bits)> = atoms
+            .iter()
+            .filter(|(c, _)| !matches!(c, Self::Count(..)))
+            .map(|(c, b)| (Self::Not(Box::new(c.clone())), b.not(len)))
+            .collect();
+        atoms.extend(negated);
+        atoms.sort_by_key(|(c, _)| c.size());
+
+        // the smallest, then most accurate, condition within tolerance so far
+        let mut best: Option<(Self, usize)> = None;
+        let offer = |best: &mut Option<(Self, usize)>, c: Self, bits: &Bits| {
+            let (fp, fn_) = errors(bits);
+            let better = best
+                .as_ref()
+                .is_none_or(|(x, e)| (c.size(), fp + fn_) < (x.size(), *e));
+            if tolerance.allows(fp, fn_) && better {
+                *best = Some((c, fp + fn_));
+            }
+        };
+
+        for (c, b) in &atoms {
+            offer(&mut best, c.clone(), b);
+        }
+
+        // `A&B` misses everything either side misses, and `A|B` matches everything either side
+        // matches, so only pair up sides that are within tolerance of that on their own
+        let limit =
+            |best: &Option<(Self, usize)>| best.as_ref().map_or(max_size + 1, |x| x.0.size() + 1);
+        let wider: Vec<_> = atoms
+            .iter()
+            .filter(|(_, b)| want.excess(b) <= tolerance.false_negatives)
+            .collect();
+        let narrower: Vec<_> = atoms
+            .iter()
+            .filter(|(_, b)| b.excess(&want) <= tolerance.false_positives)
+            .collect();
+        for (list, and) in [(wider, true), (narrower, false)] {
+            // past the deadline, settle for the best condition found so far
+            for (i, ((a, x), (b, y))) in list
+                .iter()
+                .map(|x| (&x.0, &x.1))
+                .tuple_combinations()
+                .enumerate()
+            {
+                if i % DEADLINE_CHECKS == 0 && deadline.is_some_and(|x| Instant::now() >= x) {
+                    break;
+                }
+
+                if 1 + a.size() + b.size() >= limit(&best) {
+                    continue;
+                }
+
+                let (bits, c) = if and {
+                    (
+                        x.and(y),
+                        Self::And(Box::new(a.clone()), Box::new(b.clone())),
+                    )
+                } else {
+                    (x.or(y), Self::Or(Box::new(a.clone()), Box::new(b.clone())))
+                };
+
+                if fits(&bits) {
+                    offer(&mut best, c, &bits);
+                }
+            }
+        }
+
+        // the bits came from compiled matchers, so double check with the real thing
+        best.map(|(x, _)| x).filter(|x| {
+            let bits = Bits::new(queues.iter().map(|q| x.has(q)));
+            x.size() <= max_size && fits(&bits)
+        })
+    }
+
+    // Orderings, counts and positions of single pieces and pairs of pieces
+    fn atoms(max_len: usize) -> Vec<Self> {
+        let pieces = B::wildcard();
+        let singles: Vec<Pattern<B>> = pieces.iter().map(|&x| Pattern::Single(x)).collect();
+        let sets: Vec<Pattern<B>> = singles
+            .iter()
+            .cloned()
+            .chain(
+                pieces
+                    .iter()
+                    .tuple_combinations()
+                    .map(|(&a, &b)| Pattern::Any(vec![Pattern::Single(a), Pattern::Single(b)])),
+            )
+            .collect();
+
+        let mut v = vec![];
+        for (a, b) in singles.iter().tuple_combinations() {
+            v.push(Self::Before(a.clone(), b.clone()));
+            v.push(Self::Before(b.clone(), a.clone()));
+        }
+
+        let max = max_len as isize;
+        for p in &sets {
+            for n in 0..=max_len {
+                v.push(Self::Count(p.clone(), Compare::Eq(n)));
+                v.push(Self::Count(p.clone(), Compare::Le(n)));
+                v.push(Self::Count(p.clone(), Compare::Ge(n)));
+            }
+
+            for i in 0..max {
+                v.push(Self::Location(p.clone(), vec![Place::At(i)]));
+                v.push(Self::Location(p.clone(), vec![Place::At(-i - 1)]));
+                v.push(Self::Location(
+                    p.clone(),
+                    vec![Place::Range(0, Some(i + 1))],
+                ));
+                v.push(Self::Location(p.clone(), vec![Place::Range(-i - 1, None)]));
+            }
+        }
+
+        v
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::HashSet;
+
+use itertools::Itertools;
+
+use crate::{bag::Bag, parallel, pattern::Pattern, queue::Queue};
+
+// each batch holds this many joins per queue on the left, at most
+const MAX_PARALLEL_RIGHT: usize = 256;
+
+pub type Queues<'a> = Box<dyn Iterator<Item = Queue> + Send + 'a>;
+
+impl<B> Pattern<B>
+where
+    B: Bag,
+{
+    // Lazily yields every queue matched by this pattern. Takes and alls of repeated items list
+    // their draws in the order `Permutations` gives, rather than by where they first turn up
+    // among all orderings
+    pub fn iter_queues(&self) -> Queues<'_> {
+        self.iter_queues_on(parallel::threads())
+    }
+
+    // The same, splitting the work over at most `threads` threads
+    pub fn iter_queues_on(&self, threads: usize) -> Queues<'_> {
+        match self {
+            Self::Single(c) => Box::new(std::iter::once(Queue::new(vec![*c]))),
+            Self::Either(t, u) => {
+                Box::new(t.iter_queues_on(threads).chain(u.iter_queues_on(threads)))
+            }
+            // joining onto a short right side is split up between threads a batch at a time
+            Self::Seq(t, u) if threads > 1 && u.count_bound() <= MAX_PARALLEL_RIGHT => Box::new(
+                parallel::flat_map_on(threads, t.iter_queues_on(threads), move |tq| {
+                    u.iter_queues_on(threads)
+                        .map(|uq| tq.join(uq))
+                        .collect::<Vec<_>>()
+                }),
+            ),
+            Self::Seq(t, u) => Box::new(
+                t.iter_queues_on(threads)
+                    .flat_map(move |tq| u.iter_queues_on(threads).map(move |uq| tq.join(uq))),
+            ),
+            Self::Any(t) => Box::new(t.iter().flat_map(move |x| x.iter_queues_on(threads))),
+            Self::Except(t) => Box::new(Self::except(t).into_iter().map(|x| Queue::new(vec![x]))),
+            Self::Group(c) => c.iter_queues_on(threads),
+            Self::Wildcard => Box::new(B::wildcard().into_iter().map(|x| Queue::new(vec![x]))),
+            Self::Take(c, n) => Self::permute(c.iter_queues_on(threads).collect(), *n),
+            Self::All(c) => {
+                let items: Vec<Queue> = c.iter_queues_on(threads).collect();
+                let l = items.len();
+                Self::permute(items, l)
+            }
+            // cut off partial queues that can't pass, if the condition allows it
+            Self::Condition(p, c) if let Some(it) = Self::iter_pruned(p, c) => it,
+            Self::Condition(p, c) if threads > 1 => Box::new(parallel::flat_map_on(
+                threads,
+                p.iter_queues_on(threads),
+                move |x| c.has(x).then(|| x.clone()),
+            )),
+            Self::Condition(p, c) => Box::new(p.iter_queues_on(threads).filter(move |x| c.has(x))),
+            Self::Unique(p) => {
+                let mut seen = HashSet::new();
+                Box::new(p.iter_queues_on(threads).filter(move |x| {
+                    let mut k = x.vec().clone();
+                    k.sort_unstable();
+                    seen.insert(k)
+                }))
+            }
+        }
+    }
+
+    // At least as many as the queues this pattern matches, from its structure alone, so without
+    // going through any conditions
+    fn count_bound(&self) -> usize {
+        let product = |r: std::ops::RangeInclusive<usize>| r.fold(1, usize::saturating_mul);
+        match self {
+            Self::Single(..) => 1,
+            Self::Wildcard => B::wildcard().len(),
+            Self::Except(t) => Self::except(t).len(),
+            Self::Either(a, b) => a.count_bound().saturating_add(b.count_bound()),
+            Self::Seq(a, b) => a.count_bound().saturating_mul(b.count_bound()),
+            Self::Any(t) => t.iter().fold(0, |n, x| n.saturating_add(x.count_bound())),
+            Self::Group(p) | Self::Condition(p, _) | Self::Unique(p) => p.count_bound(),
+            Self::Take(p, n) => match p.count_bound() {
+                m if *n > m => 0,
+                m => product(m - n + 1..=m),
+            },
+            Self::All(p) => product(1..=p.count_bound()),
+        }
+    }
+
+    fn permute(items: Vec<Queue>, k: usize) -> Queues<'static> {
+        // distinct sequences of equal-length items always join into distinct queues
+        if items.iter().map(Queue::len).all_equal() {
+            Box::new(Permutations::new(items, k))
+        } else {
+            let mut seen = HashSet::new();
+            Box::new(Permutations::new(items, k).filter(move |x| seen.insert(x.clone())))
+        }
+    }
+}
+
+// Generates the distinct `k`-permutations of a multiset of queues one at a time, in
+// lexicographic order of where each item first appears, so `[TIT]2` gives TT, TI, IT
+pub struct Permutations {
+    items: Vec<Queue>,
+    left: Vec<usize>,
+    current: Vec<usize>,
+    k: usize,
+    started: bool,
+    done: bool,
+}
+
+impl Permutations {
+    pub fn new(queues: Vec<Queue>, k: usize) -> Self {
+        let mut items: Vec<Queue> = vec![];
+        let mut left: Vec<usize> = vec![];
+        for q in queues {
+            match items.iter().position(|x| *x == q) {
+                Some(i) => left[i] += 1,
+                None => {
+                    items.push(q);
+                    left.push(1);
+                }
+            }
+        }
+
+        Self {
+            items,
+            left,
+            current: Vec::with_capacity(k),
+            k,
+            started: false,
+            done: false,
+        }
+    }
+
+    // fills the remaining positions with the smallest available items
+    fn fill(&mut self) -> bool {
+        while self.current.len() < self.k {
+            match self.left.iter().position(|&x| x > 0) {
+                Some(j) => {
+                    self.left[j] -= 1;
+                    self.current.push(j);
+                }
+                None => return false,
+            }
+        }
+
+        true
+    }
+
+    fn advance(&mut self) -> bool {
+        while let Some(i) = self.current.pop() {
+            self.left[i] += 1;
+            if let Some(j) = (i + 1..self.items.len()).find(|&j| self.left[j] > 0) {
+                self.left[j] -= 1;
+                self.current.push(j);
+                return self.fill();
+            }
+        }
+
+        false
+    }
+
+    fn emit(&self) -> Queue {
+        Queue::new(
+            self.current
+                .iter()
+                .flat_map(|&i| self.items[i].vec().iter().copied())
+                .collect(),
+        )
+    }
+}
+
+impl Iterator for Permutations {
+    type Item = Queue;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let ok = if self.started {
+            self.advance()
+        } else {
+            self.started = true;
+            self.fill()
+        };
+
+        if ok {
+            Some(self.emit())
+        } else {
+            self.done = true;
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{bag::Bag7, pattern::Pattern};
+
+    fn expand(text: &str) -> Vec<String> {
+        Pattern::<Bag7>::new(text)
+            .unwrap()
+            .iter_queues()
+            .map(|q| format!("{q:?}"))
+            .collect()
+    }
+
+    #[test]
+    fn permutations_of_distinct_items() {
+        assert_eq!(expand("[TIO]p2"), ["TI", "TO", "IT", "IO", "OT", "OI"]);
+    }
+
+    #[test]
+    fn permutations_of_repeated_items() {
+        assert_eq!(expand("[TIT]p2"), ["TT", "TI", "IT"]);
+        assert_eq!(expand("[TIT]!"), ["TTI", "TIT", "ITT"]);
+    }
+}
@@ -2,18 +2,39 @@
 
 use clap::Parser;
 
-use crate::{bag::Bag7, program::Program};
+use crate::{
+    bag::Pieces,
+    program::{Preamble, Program},
+};
 
+pub mod approx;
 pub mod bag;
+pub mod canonical;
 pub mod condition;
-pub mod util;
+pub mod count;
+pub mod equiv;
+pub mod error;
 pub mod find;
+pub mod find_condition;
+pub mod iter;
+pub mod parallel;
 pub mod pattern;
+pub mod probability;
+pub mod prune;
 pub mod program;
 pub mod queue;
+pub mod ratio;
+pub mod rewrite;
 pub mod rule;
+pub mod saturate;
 pub mod simplify;
+pub mod simplify_condition;
 
 fn main() {
-    Program::<Bag7>::parse().run();
+    if let Err(e) = Preamble::pieces().and_then(|x| x.map_or(Ok(()), |p| Pieces::define(&p))) {
+        eprintln!("error: {e}");
+        std::process::exit(2);
+    }
+
+    Program::<Pieces>::parse().run();
 }
@@ -0,0 +1,201 @@
+use std::{cell::Cell, num::NonZeroUsize, sync::OnceLock, thread};
+
+static THREADS: OnceLock<usize> = OnceLock::new();
+
+// how many items of a stream are pulled in before handing them out to the workers. fixed, so
+// that the output (and where a budget cuts things off) doesn't depend on the thread count
+pub const BATCH: usize = 4096;
+
+thread_local! {
+    // whether this thread is one of the workers, which run any parallel work of their own
+    // in place rather than starting more threads
+    static WORKER: Cell<bool> = const { Cell::new(false) };
+}
+
+// Sets the number of worker threads; only the first call counts
+pub fn set_threads(n: NonZeroUsize) {
+    let _ = THREADS.set(n.get());
+}
+
+// The number of worker threads, defaulting to one per core
+pub fn threads() -> usize {
+    *THREADS.get_or_init(|| thread::available_parallelism().map_or(1, NonZeroUsize::get))
+}
+
+// Runs `f` over every item, splitting them into contiguous runs over the worker threads, and
+// returns the results in the same order as the items
+pub fn map<T, R, F>(items: &[T], f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    map_on(threads(), items, f)
+}
+
+// `map` over at most `n` threads, or just this one if it is a worker already
+fn map_on<T, R, F>(n: usize, items: &[T], f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let n = n.min(items.len());
+    if n <= 1 || WORKER.get() {
+        return items.iter().map(f).collect();
+    }
+
+    let f = &f;
+    thread::scope(|s| {
+        let handles: Vec<_> = items
+            .chunks(items.len().div_ceil(n))
+            .map(|c| {
+                s.spawn(move || {
+                    WORKER.set(true);
+                    c.iter().map(f).collect::<Vec<_>>()
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().expect("worker thread panicked"))
+            .collect()
+    })
+}
+
+// Like `Iterator::flat_map`, but runs `f` on at most `n` worker threads a batch at a time
+pub fn flat_map_on<I, O, F>(n: usize, iter: I, f: F) -> FlatMap<I, O, F>
+where
+    I: Iterator,
+    I::Item: Sync,
+    O: IntoIterator<Item: Send> + Send,
+    F: Fn(&I::Item) -> O + Sync,
+{
+    FlatMap {
+        iter,
+        f,
+        threads: n,
+        ready: vec![].into_iter(),
+    }
+}
+
+pub struct FlatMap<I, O, F>
+where
+    O: IntoIterator,
+{
+    iter: I,
+    f: F,
+    threads: usize,
+    ready: std::vec::IntoIter<O::Item>,
+}
+
+impl<I, O, F> Iterator for FlatMap<I, O, F>
+where
+    I: Iterator,
+    I::Item: Sync,
+    O: IntoIterator<Item: Send> + Send,
+    F: Fn(&I::Item) -> O + Sync,
+{
+    type Item = O::Item;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(x) = self.ready.next() {
+                return Some(x);
+            }
+
+            let batch: Vec<I::Item> = self.iter.by_ref().take(BATCH).collect();
+            if batch.is_empty() {
+                return None;
+            }
+
+            let f = &self.f;
+            self.ready = map_on(self.threads, &batch, |x| {
+                f(x).into_iter().collect::<Vec<_>>()
+            })
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .into_iter();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::{FlatMap, map_on};
+
+    #[test]
+    fn map_keeps_order() {
+        let items: Vec<usize> = (0..10_000).collect();
+        let sequential = map_on(1, &items, |x| x * 3);
+        assert_eq!(map_on(4, &items, |x| x * 3), sequential);
+        assert_eq!(map_on(7, &items, |x| x * 3), sequential);
+    }
+
+    #[test]
+    fn flat_map_keeps_order() {
+        let run = |threads| {
+            FlatMap {
+                iter: 0..10_000usize,
+                f: |x: &usize| vec![*x; x % 3],
+                threads,
+                ready: vec![].into_iter(),
+            }
+            .collect::<Vec<_>>()
+        };
+        assert_eq!(run(4), run(1));
+    }
+
+    #[test]
+    fn expand_matches_sequential_queues() {
+        use crate::{bag::Bag7, pattern::Pattern, queue::Queue};
+
+        // joins and filters one queue at a time, the way `iter_queues` does on a single thread
+        fn sequential(p: &Pattern<Bag7>) -> Vec<Queue> {
+            match p {
+                Pattern::Group(x) => sequential(x),
+                Pattern::Either(a, b) => [sequential(a), sequential(b)].concat(),
+                Pattern::Seq(a, b) => {
+                    let right = sequential(b);
+                    sequential(a)
+                        .iter()
+                        .flat_map(|x| right.iter().map(|y| x.join(y.clone())))
+                        .collect()
+                }
+                Pattern::Condition(a, c) => {
+                    sequential(a).into_iter().filter(|x| c.has(x)).collect()
+                }
+                x => x.iter_queues_on(1).collect(),
+            }
+        }
+
+        for text in ["*p3*p2", "(*p3;[TI]2)*", "*p4{T<I}*", "(T;*p2)*p2{#O=1}"] {
+            let p = Pattern::<Bag7>::new(text).unwrap();
+            for threads in [1, 4] {
+                let expanded: Vec<_> = p.iter_queues_on(threads).collect();
+                assert_eq!(expanded, sequential(&p), "{text} on {threads} threads");
+            }
+        }
+    }
+
+    #[test]
+    fn nested_work_stays_on_the_worker() {
+        let outer: Vec<usize> = (0..8).collect();
+        let nested = map_on(4, &outer, |x| {
+            let here = thread::current().id();
+            let inner: Vec<usize> = (0..100).collect();
+            map_on(4, &inner, |y| {
+                assert_eq!(thread::current().id(), here);
+                x * 100 + y
+            })
+        });
+        let sequential: Vec<Vec<usize>> = outer
+            .iter()
+            .map(|x| (0..100).map(|y| x * 100 + y).collect())
+            .collect();
+        assert_eq!(nested, sequential);
+    }
+}
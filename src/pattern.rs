@@ -4,12 +4,19 @@ use chumsky::{
     IterParser, Parser,
     error::Rich,
     extra::Err,
-    prelude::{choice, group, just, one_of, recursive},
+    prelude::{any, choice, group, just, recursive},
     text::digits,
 };
-use itertools::Itertools;
 
-use crate::{bag::Bag, condition::Condition, util::FullDedup, queue::Queue};
+use crate::{
+    approx::Tolerance,
+    bag::Bag,
+    canonical::rank,
+    condition::Condition,
+    find::{Budget, Stats},
+    error::{Diagnostic, ParseError},
+    queue::Queue,
+};
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Pattern<B>
@@ -20,6 +27,7 @@ where
     Either(Box<Self>, Box<Self>),            // T;O
     Seq(Box<Self>, Box<Self>),               // TO
     Any(Vec<Self>),                          // [TO]
+    Except(Vec<Self>),                       // [^TO]
     Group(Box<Self>),                        // (TO)
     Wildcard,                                // *
     Take(Box<Self>, usize),                  // T2
@@ -32,19 +40,49 @@ impl<B> Pattern<B>
 where
     B: Bag,
 {
-    pub fn new(i: impl Display) -> Result<Self, String> {
+    pub fn new(i: impl Display) -> Result<Self, ParseError> {
         let v = i.to_string();
-        let p = Self::parser();
-        let r = p.parse(&v);
-        if r.has_errors() {
-            for e in r.into_errors() {
-                return Err(e.to_string());
+        Self::parser().parse(&v).into_result().map_err(|e| {
+            // the parser stops at the first problem, so check each top-level alternative on its
+            // own to report the rest too
+            let mut errors = vec![];
+            for (start, alt) in Self::alternatives_of(&v) {
+                if let Err(es) = Self::parser().parse(alt).into_result() {
+                    errors.extend(es.into_iter().map(|x| {
+                        let mut d = Diagnostic::from(x);
+                        d.span = d.span.start + start..d.span.end + start;
+                        d
+                    }));
+                }
+            }
+
+            if errors.is_empty() {
+                ParseError::new(&v, e)
+            } else {
+                ParseError::from_diagnostics(&v, errors)
             }
+        })
+    }
 
-            std::process::exit(1);
+    // Splits source at `;` and newlines that are not nested in any brackets
+    fn alternatives_of(source: &str) -> Vec<(usize, &str)> {
+        let mut v = vec![];
+        let mut depth = 0isize;
+        let mut start = 0;
+        for (i, c) in source.char_indices() {
+            match c {
+                '(' | '[' | '{' => depth += 1,
+                ')' | ']' | '}' => depth -= 1,
+                ';' | '\n' if depth == 0 => {
+                    v.push((start, &source[start..i]));
+                    start = i + 1;
+                }
+                _ => {}
+            }
         }
 
-        Ok(p.parse(&v).unwrap())
+        v.push((start, &source[start..]));
+        v
     }
 
     pub fn parser<'a>() -> impl Parser<'a, &'a str, Self, Err<Rich<'a, char>>>
@@ -52,28 +90,51 @@ where
         B: 'a,
     {
         recursive(|a| {
-            let number = digits(10)
-                .collect::<String>()
-                .from_str::<usize>()
-                .unwrapped();
+            let number = digits(10).collect::<String>().try_map(|x, span| {
+                x.parse::<usize>()
+                    .map_err(|e| Rich::custom(span, format!("{x}: {e}")))
+            });
             let wildcard = just('*').map(|_| Self::Wildcard);
-            let single = one_of(B::wildcard()).map(Self::Single);
+            // any letter is read as a piece, so one outside the bag gets a precise error
+            let single = any()
+                .filter(|c: &char| B::has(*c) || (c.is_alphabetic() && *c != 'p'))
+                .validate(|c, e, emitter| {
+                    if !B::has(c) {
+                        emitter.emit(Rich::custom(
+                            e.span(),
+                            format!(
+                                "{c:?} is not a piece, expected one of {}",
+                                B::wildcard().iter().collect::<String>()
+                            ),
+                        ));
+                    }
+
+                    Self::Single(c)
+                })
+                .labelled("piece");
             let gr = a
                 .delimited_by(just('('), just(')'))
                 .map(|x| Self::Group(Box::new(x)));
 
             let atom = choice((single, wildcard, gr)).boxed();
 
+            let except = just('^')
+                .ignore_then(atom.clone().repeated().collect())
+                .delimited_by(just('['), just(']'))
+                .map(Self::Except);
+
             let any = atom
                 .clone()
                 .repeated()
                 .collect()
                 .delimited_by(just('['), just(']'))
                 .map(|x| Self::Any(x))
+                .or(except)
                 .or(atom.clone())
                 .boxed();
 
-            let all = group((any.clone(), just('!')))
+            // `!=` belongs to a count comparison, not to `All`
+            let all = group((any.clone(), just('!').then_ignore(just('=').not())))
                 .map(|(a, _)| Self::All(Box::new(a)))
                 .or(any.clone())
                 .boxed();
@@ -126,6 +187,7 @@ where
             Self::Either(t, u) => t.count() + u.count(),
             Self::Seq(t, u) => t.count() * u.count(),
             Self::Any(t) => t.iter().map(|x| x.count()).sum(),
+            Self::Except(t) => Self::except(t).len(),
             Self::Group(p) => p.count(),
             Self::Wildcard => B::wildcard().len(),
             Self::Take(p, n) => {
@@ -134,91 +196,75 @@ where
             }
 
             Self::All(p) => (1..=p.count()).product(),
-            Self::Condition(..) | Self::Unique(..) => self.queues().len(),
+            Self::Condition(..) | Self::Unique(..) => self
+                .count_structural()
+                .unwrap_or_else(|| self.iter_queues().count()),
         }
     }
 
+    // The pieces of the bag that none of `t` match on their own
+    pub fn except(t: &[Self]) -> Vec<char> {
+        let excluded: HashSet<Queue> = t.iter().flat_map(|x| x.iter_queues()).collect();
+        B::wildcard()
+            .into_iter()
+            .filter(|x| !excluded.contains(&Queue::new(vec![*x])))
+            .collect()
+    }
+
+    // A pattern that matches nothing at all: none of the pieces
+    pub fn empty() -> Self {
+        Self::Except(B::wildcard().into_iter().map(Self::Single).collect())
+    }
+
     pub fn set(&self) -> HashSet<Queue> {
         self.queues().into_iter().collect()
     }
 
     pub fn queues(&self) -> Vec<Queue> {
-        match self {
-            // Self::Phantom(..) => unsafe { std::hint::unreachable_unchecked() },
-            Self::Single(c) => vec![Queue::new(vec![*c])],
-            Self::Either(t, u) => [t.queues(), u.queues()].concat(),
-            Self::Seq(t, u) => {
-                let mut v = vec![];
-                for tq in t.queues() {
-                    for uq in u.queues() {
-                        v.push(tq.join(uq))
-                    }
-                }
-
-                v
-            }
-
-            Self::Any(t) => {
-                let mut v = vec![];
-                for tq in t {
-                    for q in tq.queues() {
-                        v.push(q);
-                    }
-                }
-
-                v
-            }
-
-            Self::Group(c) => c.queues(),
-            Self::Wildcard => B::wildcard().iter().map(|x| Queue::new(vec![*x])).collect(),
-            Self::Take(c, b) => c
-                .queues()
-                .into_iter()
-                .permutations(*b)
-                .map(|x| Queue::new(x.into_iter().map(|x| x.vec().clone()).flatten().collect()))
-                .collect::<Vec<_>>()
-                .full_dedup(),
-            Self::All(c) => {
-                let q = c.queues();
-                let l = q.len();
-                q.iter()
-                    .permutations(l)
-                    .map(|x| Queue::new(x.into_iter().map(|x| x.vec().clone()).flatten().collect()))
-                    .collect::<Vec<_>>()
-                    .full_dedup()
-            }
-            Self::Condition(p, c) => p.queues().into_iter().filter(|x| c.has(x)).collect(),
-            Self::Unique(p) => {
-                let mut v = vec![];
-                for q in p.queues() {
-                    if !v.iter().any(|x| q.loose_eq(x)) {
-                        v.push(q);
-                    }
-                }
-
-                v
-            }
-        }
+        self.iter_queues().collect()
     }
 
     pub fn find(universe: &[Queue], set: &[Queue], opt_level: Optimization) -> Option<Self> {
+        Self::find_within(
+            universe,
+            set,
+            opt_level,
+            Tolerance::default(),
+            Budget::default(),
+        )
+        .0
+    }
+
+    // Like `find`, but settles for the smallest pattern that gets at most `tolerance` of the
+    // universe wrong, and gives up searching once `budget` runs out
+    pub fn find_within(
+        universe: &[Queue],
+        set: &[Queue],
+        opt_level: Optimization,
+        tolerance: Tolerance,
+        budget: Budget,
+    ) -> (Option<Self>, Stats) {
         match opt_level {
-            Optimization::Exhaustive => Self::find_exhaustive(universe, set),
-            _ => todo!(),
+            Optimization::None => (Self::find_literal(set), Stats::default()),
+            _ => Self::find_exhaustive(universe, set, opt_level, tolerance, budget),
         }
     }
 
-    // Checks if candidate matches all of `set` and none outside of `set` in `universe`
-    pub fn check(&self, _universe: &[Queue], set: &[Queue]) -> bool {
-        let qs = self.queues();
+    // Checks if candidate matches all of `set` and none outside of `set` in `universe`; queues
+    // outside of `universe` are don't-cares
+    pub fn check(&self, universe: &[Queue], set: &[Queue]) -> bool {
+        let qs = self.set();
+        let universe: HashSet<&Queue> = universe.iter().collect();
+        let set: HashSet<&Queue> = set.iter().collect();
 
-        qs.iter().all(|x| set.contains(x)) && !qs.iter().any(|x| !set.contains(x))
+        set.iter().all(|x| qs.contains(*x))
+            && qs.iter().all(|x| !universe.contains(x) || set.contains(x))
     }
 
     pub fn size(&self) -> usize {
         match self {
             Self::All(c) => 1 + c.size(),
-            Self::Any(c) => 1 + c.len(),
+            Self::Any(c) | Self::Except(c) => 1 + c.len(),
             Self::Condition(c, p) => 1 + c.size() + p.size(),
             Self::Either(a, b) => 1 + a.size() + b.size(),
             Self::Group(c) => 1 + c.size(),
@@ -237,24 +283,38 @@ where
     B: Bag,
 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // rewrites can leave an operand that binds looser than its place allows, so put back the
+        // parentheses that reading it again would need (`;` and sequences are associative, so
+        // a chain nested to the right doesn't need any)
+        let operand = |p: &Self, max: usize| {
+            if rank(p) > max {
+                format!("({p})")
+            } else {
+                p.to_string()
+            }
+        };
         write!(
             f,
             "{}",
             match self {
                 // Self::Phantom(..) => String::new(),
                 Self::Single(p) => format!("{p}"),
-                Self::Either(t, u) => format!("{t};{u}"),
-                Self::Seq(t, u) => format!("{t}{u}"),
+                Self::Either(t, u) => format!("{};{}", operand(t, 6), operand(u, 6)),
+                Self::Seq(t, u) => format!("{}{}", operand(t, 4), operand(u, 4)),
                 Self::Any(t) => format!(
                     "[{}]",
-                    t.iter().map(|x| x.to_string()).collect::<Vec<_>>().join("")
+                    t.iter().map(|x| operand(x, 0)).collect::<Vec<_>>().join("")
+                ),
+                Self::Except(t) => format!(
+                    "[^{}]",
+                    t.iter().map(|x| operand(x, 0)).collect::<Vec<_>>().join("")
                 ),
                 Self::Group(p) => format!("({p})"),
                 Self::Wildcard => "*".to_string(),
-                Self::Take(p, n) => format!("{p}{n}"),
-                Self::All(p) => format!("{p}!"),
-                Self::Condition(p, c) => format!("{p}{{{c}}}"),
-                Self::Unique(p) => format!("{p}?"),
+                Self::Take(p, n) => format!("{}{n}", operand(p, 1)),
+                Self::All(p) => format!("{}!", operand(p, 0)),
+                Self::Condition(p, c) => format!("{}{{{c}}}", operand(p, 2)),
+                Self::Unique(p) => format!("{}?", operand(p, 4)),
             }
         )
     }
@@ -264,7 +324,7 @@ impl<B> FromStr for Pattern<B>
 where
     B: Bag,
 {
-    type Err = String;
+    type Err = ParseError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         Self::new(s)
     }
@@ -273,14 +333,36 @@ where
 #[derive(Clone, Copy, Debug)]
 pub enum Optimization {
     None,
+    Quick,
     Exhaustive,
 }
 
+impl Optimization {
+    // The largest `Pattern::size` to search up to, and how many candidates to build on the way
+    pub fn effort(&self) -> (usize, usize) {
+        match self {
+            Self::None => (0, 0),
+            Self::Quick => (8, 100_000),
+            Self::Exhaustive => (16, 5_000_000),
+        }
+    }
+
+    // How many rules simplifying the literal may try, when the search comes up empty
+    pub fn fallback(&self) -> usize {
+        match self {
+            Self::None => 0,
+            Self::Quick => 10_000,
+            Self::Exhaustive => 1_000_000,
+        }
+    }
+}
+
 impl FromStr for Optimization {
     type Err = Infallible;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "0" => Ok(Self::None),
+            "1" => Ok(Self::Quick),
             _ => Ok(Self::Exhaustive),
         }
     }
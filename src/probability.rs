@@ -0,0 +1,242 @@
+use std::{collections::HashSet, str::FromStr};
+
+use itertools::Itertools;
+
+use crate::{bag::Bag, pattern::Pattern, queue::Queue, ratio::Ratio};
+
+// How pieces are dealt
+#[derive(Clone, Debug, PartialEq)]
+pub enum Randomizer {
+    // Shuffled copies of the whole bag, one after another. `offset` pieces of the current bag
+    // have been dealt already; `seen` says which, or `None` if that isn't known.
+    Bag {
+        offset: usize,
+        seen: Option<Vec<char>>,
+    },
+    // Every piece is drawn independently and uniformly
+    Random,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Model {
+    Bag,
+    Random,
+}
+
+impl FromStr for Model {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bag" => Ok(Self::Bag),
+            "random" => Ok(Self::Random),
+            _ => Err(format!(
+                "unknown randomizer {s:?}, expected `bag` or `random`"
+            )),
+        }
+    }
+}
+
+impl Randomizer {
+    pub fn new<B: Bag>(
+        model: Model,
+        offset: Option<usize>,
+        seen: Option<&str>,
+    ) -> Result<Self, String> {
+        match model {
+            Model::Random if offset.is_some() || seen.is_some() => {
+                Err("the random randomizer has no bag to be partway through".to_string())
+            }
+            Model::Random => Ok(Self::Random),
+            Model::Bag => {
+                let bag = B::wildcard();
+                let seen = match seen {
+                    Some(s) => {
+                        let q =
+                            Queue::checked::<B>(s.chars().collect()).map_err(|e| e.to_string())?;
+                        if !q.vec().iter().all_unique() {
+                            return Err(format!(
+                                "{s:?} repeats a piece, so it can't be from one bag"
+                            ));
+                        }
+
+                        Some(q.vec().clone())
+                    }
+                    None => None,
+                };
+
+                let offset = match (offset, &seen) {
+                    (Some(o), Some(s)) if o != s.len() => {
+                        return Err(format!(
+                            "offset {o} disagrees with the {} seen pieces",
+                            s.len()
+                        ));
+                    }
+                    (Some(o), _) => o,
+                    (None, Some(s)) => s.len(),
+                    (None, None) => 0,
+                };
+
+                if offset >= bag.len() {
+                    return Err(format!(
+                        "offset must be less than the bag size {}",
+                        bag.len()
+                    ));
+                }
+
+                Ok(Self::Bag { offset, seen })
+            }
+        }
+    }
+}
+
+impl<B> Pattern<B>
+where
+    B: Bag,
+{
+    // The chance that the upcoming pieces start with some queue this pattern matches, or an
+    // error if it takes more than 128 bits to write down exactly
+    pub fn probability(&self, randomizer: &Randomizer) -> Result<Ratio, String> {
+        let queues = Self::prefix_free(self.set());
+        let bag = B::wildcard();
+        let sum = |x: &[Option<Ratio>]| {
+            x.iter()
+                .try_fold(Ratio::ZERO, |a, b| a.checked_add((*b)?))
+                .ok_or_else(|| "the probability is too fine to compute exactly".to_string())
+        };
+
+        match randomizer {
+            Randomizer::Random => {
+                let terms: Vec<Option<Ratio>> = queues
+                    .iter()
+                    .filter(|q| q.vec().iter().all(|&x| B::has(x)))
+                    .map(|q| {
+                        let den = (bag.len() as u128).checked_pow(q.len() as u32)?;
+                        Some(Ratio::new(1, den))
+                    })
+                    .collect();
+                sum(&terms)
+            }
+            Randomizer::Bag {
+                seen: Some(seen), ..
+            } => {
+                let left: Vec<char> = bag.iter().copied().filter(|x| !seen.contains(x)).collect();
+                let terms: Vec<Option<Ratio>> =
+                    queues.iter().map(|q| Self::dealt(q, &left)).collect();
+                sum(&terms)
+            }
+            Randomizer::Bag { offset, seen: None } => {
+                // every set of already-dealt pieces is equally likely
+                let mut terms = vec![];
+                let mut ways = 0;
+                for left in bag.iter().copied().combinations(bag.len() - offset) {
+                    terms.extend(queues.iter().map(|q| Self::dealt(q, &left)));
+                    ways += 1;
+                }
+
+                sum(&terms)?
+                    .checked_mul(Ratio::new(1, ways))
+                    .ok_or_else(|| "the probability is too fine to compute exactly".to_string())
+            }
+        }
+    }
+
+    // The chance a bag randomizer deals `queue` next, with `left` still in the current bag, if
+    // it fits
+    fn dealt(queue: &Queue, left: &[char]) -> Option<Ratio> {
+        let mut left = left.to_vec();
+        let mut p = Ratio::ONE;
+        for &c in queue.vec() {
+            if left.is_empty() {
+                left = B::wildcard();
+            }
+
+            let Some(i) = left.iter().position(|&x| x == c) else {
+                return Some(Ratio::ZERO);
+            };
+
+            p = p.checked_mul(Ratio::new(1, left.len() as u128))?;
+            left.remove(i);
+        }
+
+        Some(p)
+    }
+
+    // Drops queues that extend another queue in the set, since dealing the shorter one
+    // already satisfies the pattern
+    fn prefix_free(set: HashSet<Queue>) -> Vec<Queue> {
+        set.iter()
+            .filter(|q| !(0..q.len()).any(|l| set.contains(&q.slice(0, l))))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        bag::Bag7,
+        pattern::Pattern,
+        probability::{Model, Randomizer},
+        ratio::Ratio,
+    };
+
+    fn chance(pattern: &str, model: Model, offset: Option<usize>, seen: Option<&str>) -> Ratio {
+        let r = Randomizer::new::<Bag7>(model, offset, seen).unwrap();
+        Pattern::<Bag7>::new(pattern)
+            .unwrap()
+            .probability(&r)
+            .unwrap()
+    }
+
+    #[test]
+    fn fresh_bag() {
+        assert_eq!(chance("T", Model::Bag, None, None), Ratio::new(1, 7));
+        assert_eq!(chance("TI", Model::Bag, None, None), Ratio::new(1, 42));
+        assert_eq!(chance("TT", Model::Bag, None, None), Ratio::ZERO);
+        assert_eq!(chance("*p7", Model::Bag, None, None), Ratio::ONE);
+    }
+
+    #[test]
+    fn seen_pieces() {
+        assert_eq!(chance("T", Model::Bag, None, Some("TIL")), Ratio::ZERO);
+        assert_eq!(chance("O", Model::Bag, None, Some("TIL")), Ratio::new(1, 4));
+        assert_eq!(
+            chance("TT", Model::Bag, None, Some("ILJOSZ")),
+            Ratio::new(1, 7)
+        );
+        assert_eq!(
+            chance("TT", Model::Bag, Some(6), Some("ILJOSZ")),
+            Ratio::new(1, 7)
+        );
+    }
+
+    #[test]
+    fn unknown_offset() {
+        // the one piece left is equally likely to be any of them
+        assert_eq!(chance("T", Model::Bag, Some(6), None), Ratio::new(1, 7));
+        assert_eq!(chance("TT", Model::Bag, Some(6), None), Ratio::new(1, 49));
+        assert_eq!(chance("TI", Model::Bag, Some(5), None), Ratio::new(1, 42));
+    }
+
+    #[test]
+    fn bad_offsets() {
+        assert!(Randomizer::new::<Bag7>(Model::Bag, Some(7), None).is_err());
+        assert!(Randomizer::new::<Bag7>(Model::Bag, Some(2), Some("TIL")).is_err());
+        assert!(Randomizer::new::<Bag7>(Model::Bag, None, Some("TT")).is_err());
+        assert!(Randomizer::new::<Bag7>(Model::Random, Some(1), None).is_err());
+    }
+
+    #[test]
+    fn random() {
+        assert_eq!(chance("T", Model::Random, None, None), Ratio::new(1, 7));
+        assert_eq!(chance("TT", Model::Random, None, None), Ratio::new(1, 49));
+        assert_eq!(chance("[TI]", Model::Random, None, None), Ratio::new(2, 7));
+    }
+
+    #[test]
+    fn long_queues_fail_instead_of_overflowing() {
+        let long = |n: usize| Pattern::<Bag7>::new("T".repeat(n)).unwrap();
+        assert!(long(45).probability(&Randomizer::Random).is_ok());
+        assert!(long(46).probability(&Randomizer::Random).is_err());
+    }
+}
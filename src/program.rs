@@ -1,8 +1,24 @@
-use std::{ops::Deref, str::FromStr, time::Instant};
+use std::{
+    io::Write,
+    num::NonZeroUsize,
+    ops::Deref,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
 
 use crate::{
+    approx::Tolerance,
     bag::Bag,
+    error::ParseError,
+    find::Budget,
+    parallel,
     pattern::{Optimization, Pattern},
+    probability::{Model, Randomizer},
+    rewrite,
+    saturate::{Cost, Strategy},
+    simplify::Settings,
 };
 
 #[derive(clap::Parser, Clone)]
@@ -36,6 +52,32 @@ where
     Simplify {
         #[arg(short = 'p', long = "pattern")]
         pattern: Text<Pattern<B>>,
+        #[arg(long = "verify", default_value_t = false)]
+        verify: bool,
+        #[arg(long = "strategy", default_value = "greedy")]
+        strategy: Strategy,
+        #[arg(long = "cost", default_value = "size")]
+        cost: Cost,
+        #[arg(long = "rules")]
+        rules: Option<String>,
+    },
+    Prob {
+        #[arg(short = 'p', long = "pattern")]
+        pattern: Text<Pattern<B>>,
+        #[arg(short = 'r', long = "randomizer", default_value = "bag")]
+        model: Model,
+        #[arg(long = "offset")]
+        offset: Option<usize>,
+        #[arg(long = "seen")]
+        seen: Option<String>,
+    },
+    Equiv {
+        #[arg(short = 'a')]
+        a: Text<Pattern<B>>,
+        #[arg(short = 'b')]
+        b: Text<Pattern<B>>,
+        #[arg(short = 'm', long = "multiset", default_value_t = false)]
+        multiset: bool,
     },
     Find {
         #[arg(short = 'u', long = "universe")]
@@ -44,13 +86,62 @@ where
         set: Text<Pattern<B>>,
         #[arg(short = 'O', long = "opt-level")]
         opt_level: Optimization,
+        #[arg(long = "false-positives", default_value_t = 0)]
+        false_positives: usize,
+        #[arg(long = "false-negatives", default_value_t = 0)]
+        false_negatives: usize,
+        #[arg(long = "timeout")]
+        timeout: Option<f64>,
+        #[arg(long = "max-candidates")]
+        max_candidates: Option<usize>,
     },
 }
 
 #[derive(clap::Args, Clone)]
 pub struct Extra {
-    #[arg(short = 't', long = "timing", default_value_t = false)]
+    #[arg(short = 't', long = "timing", default_value_t = false, global = true)]
     timing: bool,
+    #[arg(long = "pieces", global = true)]
+    pieces: Option<String>,
+    #[arg(long = "threads", global = true)]
+    threads: Option<NonZeroUsize>,
+}
+
+// Parsed ahead of `Program`, since the piece set decides how pattern arguments are parsed
+#[derive(clap::Parser)]
+#[command(ignore_errors = true)]
+pub struct Preamble {
+    #[command(flatten)]
+    extra: Extra,
+}
+
+impl Preamble {
+    // The pieces given with `--pieces`, read from a file when prefixed with `file:`
+    pub fn pieces() -> Result<Option<String>, String> {
+        // `--pieces` is global, so it may come after the subcommand and its arguments, which
+        // this parser would stop at; only hand it the program name and the flag itself
+        let mut args = std::env::args();
+        let mut kept: Vec<String> = args.next().into_iter().collect();
+        while let Some(arg) = args.next() {
+            if arg == "--pieces" {
+                kept.push(arg);
+                kept.extend(args.next());
+            } else if arg.starts_with("--pieces=") {
+                kept.push(arg);
+            }
+        }
+
+        let Some(spec) = Self::try_parse_from(kept).ok().and_then(|x| x.extra.pieces) else {
+            return Ok(None);
+        };
+
+        match spec.strip_prefix("file:") {
+            Some(path) => std::fs::read_to_string(path)
+                .map(Some)
+                .map_err(|e| format!("could not read {path}: {e}")),
+            None => Ok(Some(spec)),
+        }
+    }
 }
 
 impl<B> Program<B>
@@ -58,15 +149,42 @@ where
     B: Bag,
 {
     pub fn run(self) {
+        if let Some(n) = self.extra.threads {
+            parallel::set_threads(n);
+        }
+
         let i = Instant::now();
         match self.cmd {
             Cmd::Expand { pattern } => {
-                for q in pattern.queues() {
-                    println!("{q:?}");
+                let mut out = std::io::stdout().lock();
+                for q in pattern.iter_queues() {
+                    if writeln!(out, "{q:?}").is_err() {
+                        break;
+                    }
                 }
             }
-            Cmd::Simplify { pattern } => {
-                let x = pattern.simplify();
+            Cmd::Simplify {
+                pattern,
+                verify,
+                strategy,
+                cost,
+                rules,
+            } => {
+                if let Some(path) = rules
+                    && let Err(e) = rewrite::load::<B>(&path)
+                {
+                    eprintln!("error: {e}");
+                    std::process::exit(1);
+                }
+
+                let x = match strategy {
+                    Strategy::Greedy => pattern.simplify_with(Settings {
+                        verify,
+                        trace: true,
+                        ..Settings::default()
+                    }),
+                    Strategy::Saturate => pattern.simplify_saturate(cost, verify),
+                };
                 println!("{}", x);
             }
             Cmd::Ast { pattern } => {
@@ -75,15 +193,70 @@ where
             Cmd::Count { pattern } => {
                 println!("{}", pattern.count());
             }
+            Cmd::Prob {
+                pattern,
+                model,
+                offset,
+                seen,
+            } => match Randomizer::new::<B>(model, offset, seen.as_deref())
+                .and_then(|r| pattern.probability(&r))
+            {
+                Ok(p) => {
+                    println!("{p} ({:.4}%)", p.to_f64() * 100.0);
+                }
+                Err(e) => {
+                    eprintln!("error: {e}");
+                    std::process::exit(1);
+                }
+            },
+            Cmd::Equiv { a, b, multiset } => match a.difference(&b, multiset) {
+                Some(d) => println!("{d}"),
+                None => println!("equivalent"),
+            },
 
             Cmd::Find {
                 universe,
                 set,
                 opt_level,
-            } => match Pattern::<B>::find(&universe.queues(), &set.queues(), opt_level) {
-                Some(z) => println!("{z}"),
-                None => eprintln!("failed to find a pattern"),
-            },
+                false_positives,
+                false_negatives,
+                timeout,
+                max_candidates,
+            } => {
+                // the parser has already turned away pieces outside the bag, with their places
+                let (universe, set) = (universe.queues(), set.queues());
+                let tolerance = Tolerance {
+                    false_positives,
+                    false_negatives,
+                };
+                // `--timeout` is in seconds
+                let timeout = match timeout.map(Duration::try_from_secs_f64).transpose() {
+                    Ok(x) => x,
+                    Err(e) => {
+                        eprintln!("error: bad timeout: {e}");
+                        std::process::exit(1);
+                    }
+                };
+                let budget = Budget {
+                    timeout,
+                    max_candidates,
+                };
+                let (found, stats) =
+                    Pattern::<B>::find_within(&universe, &set, opt_level, tolerance, budget);
+                match found {
+                    Some(z) => {
+                        println!("{z}");
+                        if !tolerance.is_exact() {
+                            println!("{}", z.report(&universe, &set));
+                        }
+                    }
+                    None => eprintln!("failed to find a pattern"),
+                }
+
+                if stats.stopped {
+                    eprintln!("search stopped early: {stats}");
+                }
+            }
         }
 
         if self.extra.timing {
@@ -105,12 +278,18 @@ impl<T> Deref for Text<T> {
 
 impl<T> FromStr for Text<T>
 where
-    T: FromStr,
+    T: FromStr<Err = ParseError>,
 {
-    type Err = T::Err;
+    type Err = ParseError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         if let Some(path) = s.strip_prefix("file:") {
-            Ok(Self(T::from_str(&std::fs::read_to_string(path).unwrap())?))
+            let text = std::fs::read_to_string(path).map_err(|e| ParseError::Io {
+                file: path.to_string(),
+                reason: e.to_string(),
+            })?;
+            Ok(Self(
+                T::from_str(text.trim_end()).map_err(|e| e.in_file(path))?,
+            ))
         } else {
             Ok(Self(T::from_str(s)?))
         }
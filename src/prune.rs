@@ -0,0 +1,242 @@
+use crate::{
+    bag::Bag,
+    condition::Condition,
+    count::{Matcher, Segment},
+    iter::Queues,
+    pattern::Pattern,
+    queue::Queue,
+};
+
+impl<B> Pattern<B>
+where
+    B: Bag,
+{
+    // Expands `base{condition}` by building queues one piece at a time and dropping a partial
+    // queue as soon as no way of finishing it could pass the condition, rather than checking
+    // every finished queue of the base. Gives the same queues in the same order as filtering,
+    // if the base and condition can be analyzed at all
+    pub fn iter_pruned<'a>(base: &'a Self, condition: &Condition<B>) -> Option<Queues<'a>> {
+        let matcher = Matcher::new(condition)?;
+        let alternatives = base.ordered_alternatives()?;
+
+        Some(Box::new(alternatives.into_iter().flat_map(
+            move |segments| Pruned::new(matcher.clone(), &segments),
+        )))
+    }
+
+    // Like `alternatives`, but only where going through the alternatives one after another
+    // gives queues in the same order as `iter_queues`
+    fn ordered_alternatives(&self) -> Option<Vec<Vec<Segment>>> {
+        if let Some(a) = self.alternatives()
+            && a.len() == 1
+        {
+            return Some(a);
+        }
+
+        match self {
+            Self::Group(p) => p.ordered_alternatives(),
+            Self::Either(a, b) => {
+                Some([a.ordered_alternatives()?, b.ordered_alternatives()?].concat())
+            }
+            Self::Any(t) => Some(
+                t.iter()
+                    .map(|x| x.ordered_alternatives())
+                    .collect::<Option<Vec<_>>>()?
+                    .concat(),
+            ),
+            _ => None,
+        }
+    }
+}
+
+// Where a position of the queue gets its piece from
+enum Position {
+    Slot(Vec<char>),
+    // an index into the pools
+    Draw(usize),
+}
+
+// Depth-first search over the queues of one alternative, in the order `iter_queues` gives them
+struct Pruned {
+    matcher: Matcher,
+    positions: Vec<Position>,
+    // the distinct pieces of each draw and how many of each are left
+    pools: Vec<(Vec<char>, Vec<usize>)>,
+    queue: Vec<char>,
+    // the atom states after each prefix of `queue`, so one more than its length
+    states: Vec<Vec<usize>>,
+    // the next choice to try at each depth
+    next: Vec<usize>,
+    done: bool,
+}
+
+impl Pruned {
+    fn new(matcher: Matcher, segments: &[Segment]) -> Self {
+        let mut positions = vec![];
+        let mut pools = vec![];
+        for s in segments {
+            match s {
+                Segment::Slot(pieces) => positions.push(Position::Slot(pieces.clone())),
+                Segment::Draw(pieces, k) => {
+                    let mut distinct: Vec<char> = vec![];
+                    let mut left = vec![];
+                    for p in pieces {
+                        match distinct.iter().position(|x| x == p) {
+                            Some(i) => left[i] += 1,
+                            None => {
+                                distinct.push(*p);
+                                left.push(1);
+                            }
+                        }
+                    }
+
+                    positions.extend((0..*k).map(|_| Position::Draw(pools.len())));
+                    pools.push((distinct, left));
+                }
+            }
+        }
+
+        let start = matcher.start();
+        let done = !matcher.viable(&start, 0, positions.len());
+
+        Self {
+            matcher,
+            positions,
+            pools,
+            queue: vec![],
+            states: vec![start],
+            next: vec![0],
+            done,
+        }
+    }
+
+    // The piece the `i`th choice at `depth` puts down, if that choice exists and is available
+    fn choice(&self, depth: usize, i: usize) -> Option<Option<char>> {
+        match &self.positions[depth] {
+            Position::Slot(pieces) => pieces.get(i).map(|&p| Some(p)),
+            Position::Draw(d) => {
+                let (distinct, left) = &self.pools[*d];
+                distinct.get(i).map(|&p| (left[i] > 0).then_some(p))
+            }
+        }
+    }
+
+    fn push(&mut self, piece: char, i: usize) {
+        let depth = self.queue.len();
+        if let Position::Draw(d) = self.positions[depth] {
+            self.pools[d].1[i] -= 1;
+        }
+
+        let states = self
+            .matcher
+            .step(&self.states[depth], piece, depth, self.positions.len());
+        self.queue.push(piece);
+        self.states.push(states);
+        self.next.push(0);
+    }
+
+    fn pop(&mut self) {
+        self.queue.pop();
+        self.states.pop();
+        self.next.pop();
+
+        let depth = self.queue.len();
+        if let Position::Draw(d) = self.positions[depth] {
+            // the choice that was taken is the one just before the next one to try
+            self.pools[d].1[self.next[depth] - 1] += 1;
+        }
+    }
+}
+
+impl Iterator for Pruned {
+    type Item = Queue;
+    fn next(&mut self) -> Option<Self::Item> {
+        let len = self.positions.len();
+        while !self.done {
+            let depth = self.queue.len();
+            if depth == len {
+                let found = self
+                    .matcher
+                    .accepts(&self.states[depth])
+                    .then(|| Queue::new(self.queue.clone()));
+                if depth == 0 {
+                    self.done = true;
+                } else {
+                    self.pop();
+                }
+
+                if found.is_some() {
+                    return found;
+                }
+
+                continue;
+            }
+
+            let i = self.next[depth];
+            match self.choice(depth, i) {
+                None if depth == 0 => self.done = true,
+                None => self.pop(),
+                Some(None) => self.next[depth] += 1,
+                Some(Some(piece)) => {
+                    self.next[depth] += 1;
+                    self.push(piece, i);
+                    if !self.matcher.viable(&self.states[depth + 1], depth + 1, len) {
+                        self.pop();
+                    }
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{bag::Bag7, pattern::Pattern, queue::Queue};
+
+    // the pruned queues of `text`, next to those of expanding its base and then filtering
+    fn both(text: &str) -> (Vec<Queue>, Vec<Queue>) {
+        let Pattern::Condition(base, condition) = Pattern::<Bag7>::new(text).unwrap() else {
+            panic!("{text} is not a condition");
+        };
+        let pruned = Pattern::iter_pruned(&base, &condition)
+            .expect("analyzable")
+            .collect();
+        let filtered = base.iter_queues().filter(|q| condition.has(q)).collect();
+        (pruned, filtered)
+    }
+
+    #[test]
+    fn matches_filtering_at_places() {
+        for text in [
+            "*p4{@T=0}",
+            "*p4{@T=-1}",
+            "[TIO]!{@I=1}",
+            "[TTIO]p3{@T=0,2}",
+        ] {
+            let (pruned, filtered) = both(text);
+            assert_eq!(pruned, filtered, "{text}");
+        }
+    }
+
+    #[test]
+    fn matches_filtering_over_ranges() {
+        for text in ["*p4{@T=1..3}", "*p4{@T=-2..}", "[TIOS]!{@[TI]=0..=1}"] {
+            let (pruned, filtered) = both(text);
+            assert_eq!(pruned, filtered, "{text}");
+        }
+    }
+
+    #[test]
+    fn matches_filtering_on_mixed_conditions() {
+        for text in [
+            "*p4{@T=0&S<Z}",
+            "(*p3;T[IO]2){#I=1|!O<T}",
+            "([TI]*p2){#[SZ]>=1&@O=-1}",
+        ] {
+            let (pruned, filtered) = both(text);
+            assert_eq!(pruned, filtered, "{text}");
+        }
+    }
+}
@@ -2,13 +2,37 @@ use std::fmt::Debug;
 
 use itertools::Itertools;
 
-#[derive(Clone, PartialEq, Eq, Hash)]
+use crate::{bag::Bag, error::InvalidPiece};
+
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Queue(Vec<char>);
 
 impl Queue {
     pub fn new(values: Vec<char>) -> Self {
         Self(values)
     }
+    // Like `new`, but rejects anything that isn't a piece of `B`
+    pub fn checked<B: Bag>(values: Vec<char>) -> Result<Self, InvalidPiece> {
+        let q = Self(values);
+        q.validate::<B>()?;
+        Ok(q)
+    }
+
+    pub fn validate<B: Bag>(&self) -> Result<(), InvalidPiece> {
+        match self.0.iter().position(|&x| !B::has(x)) {
+            Some(index) => Err(InvalidPiece {
+                queue: self.clone(),
+                index,
+                piece: self.0[index],
+            }),
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
     pub fn len(&self) -> usize {
         self.0.len()
     }
@@ -36,9 +60,8 @@ impl Queue {
         Self(self.0[start..end].to_vec())
     }
 
-
     pub fn loose_eq(&self, rhs: &Self) -> bool {
-        self.0.iter().counts() == rhs.0.iter().counts()    
+        self.0.iter().counts() == rhs.0.iter().counts()
     }
 }
 
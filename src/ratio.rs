@@ -0,0 +1,74 @@
+use std::fmt::Display;
+
+// An exact non-negative fraction, always kept in lowest terms
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Ratio {
+    num: u128,
+    den: u128,
+}
+
+impl Ratio {
+    pub const ZERO: Self = Self { num: 0, den: 1 };
+    pub const ONE: Self = Self { num: 1, den: 1 };
+
+    pub fn new(num: u128, den: u128) -> Self {
+        assert!(den != 0, "ratio with a zero denominator");
+        let g = gcd(num, den);
+        Self {
+            num: num / g,
+            den: den / g,
+        }
+    }
+
+    pub fn num(&self) -> u128 {
+        self.num
+    }
+
+    pub fn den(&self) -> u128 {
+        self.den
+    }
+
+    // The sum, unless it doesn't fit
+    pub fn checked_add(self, rhs: Self) -> Option<Self> {
+        let g = gcd(self.den, rhs.den);
+        let den = (self.den / g).checked_mul(rhs.den)?;
+        let num = self
+            .num
+            .checked_mul(den / self.den)?
+            .checked_add(rhs.num.checked_mul(den / rhs.den)?)?;
+        Some(Self::new(num, den))
+    }
+
+    // The product, unless it doesn't fit
+    pub fn checked_mul(self, rhs: Self) -> Option<Self> {
+        // cross-reduce first to keep the intermediate products small
+        let a = gcd(self.num, rhs.den);
+        let b = gcd(rhs.num, self.den);
+        Some(Self::new(
+            (self.num / a).checked_mul(rhs.num / b)?,
+            (self.den / b).checked_mul(rhs.den / a)?,
+        ))
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.num as f64 / self.den as f64
+    }
+}
+
+fn gcd(mut a: u128, mut b: u128) -> u128 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+
+    a.max(1)
+}
+
+impl Display for Ratio {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.den == 1 {
+            write!(f, "{}", self.num)
+        } else {
+            write!(f, "{}/{}", self.num, self.den)
+        }
+    }
+}
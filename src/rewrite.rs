@@ -0,0 +1,370 @@
+use std::{any::Any, collections::HashMap, marker::PhantomData, str::FromStr, sync::OnceLock};
+
+use itertools::Itertools;
+
+use crate::{bag::Bag, condition::Condition, pattern::Pattern, rule::Rule};
+
+// metavariables are parsed as made-up pieces, taken from the private use area
+const FIRST_METAVARIABLE: u32 = 0xE000;
+const MAX_METAVARIABLES: usize = 256;
+
+// how many combinations of sample patterns to check a rule against, at most
+const MAX_CHECKS: usize = 256;
+
+// the rules loaded with `simplify --rules`, for whichever bag they were loaded for
+static USER_RULES: OnceLock<Box<dyn Any + Send + Sync>> = OnceLock::new();
+
+// A rewrite read from a rules file: `$a$b;$a$c => $a($b;$c)`. Each `$name` (lowercase, so
+// `$aT` is `$a` then `T`) stands for any pattern, the same one everywhere it appears.
+// Conditions have to match exactly
+#[derive(Clone, Debug)]
+pub struct UserRule<B: Bag> {
+    name: &'static str,
+    left: Pattern<B>,
+    right: Pattern<B>,
+    // the `$name` behind each metavariable, in order
+    names: Vec<String>,
+}
+
+// The bag `B`, plus the made-up pieces standing in for metavariables
+#[derive(Clone, Debug, PartialEq)]
+struct Meta<B: Bag>(PhantomData<B>);
+impl<B: Bag> Bag for Meta<B> {
+    fn has(piece: char) -> bool {
+        B::has(piece) || metavariable(piece).is_some()
+    }
+
+    fn wildcard() -> Vec<char> {
+        B::wildcard()
+    }
+}
+
+// Which metavariable a made-up piece stands for, if it is one
+fn metavariable(c: char) -> Option<usize> {
+    let i = (c as u32).checked_sub(FIRST_METAVARIABLE)? as usize;
+    (i < MAX_METAVARIABLES).then_some(i)
+}
+
+// Reads the rules in `path`, one `left => right` per line (blank lines and lines starting with
+// `#` are skipped), checks each one, and has every later `simplify` use them
+pub fn load<B: Bag>(path: &str) -> Result<(), String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("could not read {path}: {e}"))?;
+    let mut rules: Vec<UserRule<B>> = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let name = format!("{path}:{}", i + 1);
+        let rule = UserRule::new(&name, line).map_err(|e| format!("{name}: {e}"))?;
+        rule.check().map_err(|e| format!("{name}: {e}"))?;
+        rules.push(rule);
+    }
+
+    USER_RULES
+        .set(Box::new(rules))
+        .map_err(|_| "rules were already loaded".to_string())
+}
+
+// The loaded rules, if there are any for this bag
+pub fn user_rules<B: Bag>() -> Vec<Box<dyn Rule<B>>> {
+    USER_RULES
+        .get()
+        .and_then(|x| x.downcast_ref::<Vec<UserRule<B>>>())
+        .map_or(vec![], |x| {
+            x.iter()
+                .map(|r| Box::new(r.clone()) as Box<dyn Rule<B>>)
+                .collect()
+        })
+}
+
+impl<B: Bag> UserRule<B> {
+    pub fn new(name: &str, line: &str) -> Result<Self, String> {
+        let Some((left, right)) = line.split_once("=>") else {
+            return Err("expected `left => right`".to_string());
+        };
+
+        let mut names = vec![];
+        let left = Self::side(left, &mut names)?;
+        let known = names.len();
+        let right = Self::side(right, &mut names)?;
+        if let Some(x) = names.get(known) {
+            return Err(format!("${x} is only on the right side"));
+        }
+
+        let mut bare = &left;
+        while let Pattern::Group(p) = bare {
+            bare = p;
+        }
+
+        if let Pattern::Single(c) = bare
+            && metavariable(*c).is_some()
+        {
+            return Err("the left side can't be a lone metavariable".to_string());
+        }
+
+        Ok(Self {
+            // rules live as long as the program does, like the built-in ones
+            name: Box::leak(name.to_string().into_boxed_str()),
+            left,
+            right,
+            names,
+        })
+    }
+
+    // Parses one side of a rule, swapping each `$name` for a made-up piece
+    fn side(text: &str, names: &mut Vec<String>) -> Result<Pattern<B>, String> {
+        let mut out = String::new();
+        let mut chars = text.trim().chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '$' {
+                out.push(c);
+                continue;
+            }
+
+            let name: String = chars
+                .peeking_take_while(|x| x.is_ascii_lowercase() || x.is_ascii_digit() || *x == '_')
+                .collect();
+            if name.is_empty() {
+                return Err("expected a lowercase name after `$`".to_string());
+            }
+
+            let i = match names.iter().position(|x| *x == name) {
+                Some(i) => i,
+                None if names.len() < MAX_METAVARIABLES => {
+                    names.push(name);
+                    names.len() - 1
+                }
+                None => return Err(format!("more than {MAX_METAVARIABLES} metavariables")),
+            };
+            out.push(Self::placeholder(i));
+        }
+
+        let pattern = Pattern::<Meta<B>>::from_str(&out).map_err(|e| e.to_string())?;
+        Ok(convert(&pattern, &|_| None))
+    }
+
+    // Tries the rule out on sample patterns for its metavariables, and fails with the first
+    // sample it doesn't hold for
+    fn check(&self) -> Result<(), String> {
+        let pieces = B::wildcard();
+        let piece = |i: usize| Box::new(Pattern::Single(pieces[i % pieces.len()]));
+        let samples: Vec<Pattern<B>> = vec![
+            *piece(0),
+            *piece(1),
+            Pattern::Seq(piece(0), piece(1)),
+            Pattern::Either(piece(1), piece(2)),
+            Pattern::Any(vec![*piece(0), *piece(2)]),
+        ];
+
+        // a rule without metavariables only needs checking once
+        let combinations: Vec<Vec<&Pattern<B>>> = if self.names.is_empty() {
+            vec![vec![]]
+        } else {
+            (0..self.names.len())
+                .map(|_| samples.iter())
+                .multi_cartesian_product()
+                .take(MAX_CHECKS)
+                .collect()
+        };
+        for values in combinations {
+            let env: HashMap<char, Pattern<B>> = values
+                .iter()
+                .enumerate()
+                .map(|(i, x)| (Self::placeholder(i), (*x).clone()))
+                .collect();
+            let before = substitute(&self.left, &env);
+            let after = substitute(&self.right, &env);
+            if let Some(d) = before.difference(&after, false) {
+                let with = self
+                    .names
+                    .iter()
+                    .zip(&values)
+                    .map(|(n, x)| format!("${n} = {x}"))
+                    .join(", ");
+                return Err(format!(
+                    "rule doesn't hold with {with}: {before} vs {after}\n{d}"
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn placeholder(i: usize) -> char {
+        char::from_u32(FIRST_METAVARIABLE + i as u32).expect("in the private use area")
+    }
+
+    // Matches `pattern` against `left`, recording what each metavariable stands for
+    fn bind(left: &Pattern<B>, pattern: &Pattern<B>, env: &mut HashMap<char, Pattern<B>>) -> bool {
+        match (left, pattern) {
+            (Pattern::Single(c), _) if metavariable(*c).is_some() => match env.get(c) {
+                Some(x) => x.canonicalize() == pattern.canonicalize(),
+                None => {
+                    env.insert(*c, pattern.clone());
+                    true
+                }
+            },
+            (Pattern::Group(a), Pattern::Group(b)) => Self::bind(a, b, env),
+            (Pattern::Group(a), b) => Self::bind(a, b, env),
+            (a, Pattern::Group(b)) => Self::bind(a, b, env),
+            (Pattern::Single(a), Pattern::Single(b)) => a == b,
+            (Pattern::Wildcard, Pattern::Wildcard) => true,
+            (Pattern::Either(a, b), Pattern::Either(x, y))
+            | (Pattern::Seq(a, b), Pattern::Seq(x, y)) => {
+                Self::bind(a, x, env) && Self::bind(b, y, env)
+            }
+            (Pattern::Any(a), Pattern::Any(b)) | (Pattern::Except(a), Pattern::Except(b)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|(x, y)| Self::bind(x, y, env))
+            }
+            (Pattern::Take(a, n), Pattern::Take(b, m)) => n == m && Self::bind(a, b, env),
+            (Pattern::All(a), Pattern::All(b)) | (Pattern::Unique(a), Pattern::Unique(b)) => {
+                Self::bind(a, b, env)
+            }
+            (Pattern::Condition(a, c), Pattern::Condition(b, d)) => {
+                Self::bind(a, b, env) && c.canonicalize() == d.canonicalize()
+            }
+            _ => false,
+        }
+    }
+}
+
+impl<B: Bag> Rule<B> for UserRule<B> {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn apply(&self, pattern: &Pattern<B>) -> Pattern<B> {
+        let mut env = HashMap::new();
+        if !Self::bind(&self.left, pattern, &mut env) {
+            return pattern.clone();
+        }
+
+        // a rewrite that only moves groups around would just undo the removal of unused ones
+        let next = substitute(&self.right, &env);
+        if next.canonicalize() == pattern.canonicalize() {
+            return pattern.clone();
+        }
+
+        next
+    }
+}
+
+// Fills in the metavariables of `pattern`, grouped where needed so they keep their meaning
+fn substitute<B: Bag>(pattern: &Pattern<B>, env: &HashMap<char, Pattern<B>>) -> Pattern<B> {
+    convert(pattern, &|c| {
+        env.get(&c).map(|x| match x {
+            Pattern::Single(..)
+            | Pattern::Wildcard
+            | Pattern::Any(..)
+            | Pattern::Except(..)
+            | Pattern::Group(..) => x.clone(),
+            x => Pattern::Group(Box::new(x.clone())),
+        })
+    })
+}
+
+// Rebuilds a pattern over another bag, replacing the pieces `f` gives a pattern for
+fn convert<C: Bag, B: Bag>(
+    pattern: &Pattern<C>,
+    f: &dyn Fn(char) -> Option<Pattern<B>>,
+) -> Pattern<B> {
+    let go = |p: &Pattern<C>| Box::new(convert(p, f));
+    match pattern {
+        Pattern::Single(c) => f(*c).unwrap_or(Pattern::Single(*c)),
+        Pattern::Wildcard => Pattern::Wildcard,
+        Pattern::Either(a, b) => Pattern::Either(go(a), go(b)),
+        Pattern::Seq(a, b) => Pattern::Seq(go(a), go(b)),
+        Pattern::Any(t) => Pattern::Any(t.iter().map(|x| convert(x, f)).collect()),
+        Pattern::Except(t) => Pattern::Except(t.iter().map(|x| convert(x, f)).collect()),
+        Pattern::Group(p) => Pattern::Group(go(p)),
+        Pattern::Take(p, n) => Pattern::Take(go(p), *n),
+        Pattern::All(p) => Pattern::All(go(p)),
+        Pattern::Condition(p, c) => Pattern::Condition(go(p), Box::new(convert_condition(c, f))),
+        Pattern::Unique(p) => Pattern::Unique(go(p)),
+    }
+}
+
+fn convert_condition<C: Bag, B: Bag>(
+    condition: &Condition<C>,
+    f: &dyn Fn(char) -> Option<Pattern<B>>,
+) -> Condition<B> {
+    let go = |c: &Condition<C>| Box::new(convert_condition(c, f));
+    match condition {
+        Condition::Before(a, b) => Condition::Before(convert(a, f), convert(b, f)),
+        Condition::After(a, b) => Condition::After(convert(a, f), convert(b, f)),
+        Condition::Group(c) => Condition::Group(go(c)),
+        Condition::Count(a, n) => Condition::Count(convert(a, f), *n),
+        Condition::Location(a, places) => Condition::Location(convert(a, f), places.clone()),
+        Condition::And(a, b) => Condition::And(go(a), go(b)),
+        Condition::Or(a, b) => Condition::Or(go(a), go(b)),
+        Condition::Not(c) => Condition::Not(go(c)),
+        Condition::Phantom(..) => Condition::Phantom(PhantomData),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UserRule;
+    use crate::{bag::Bag7, pattern::Pattern, rule::Rule};
+
+    fn rule(line: &str) -> Result<UserRule<Bag7>, String> {
+        UserRule::new("test", line)
+    }
+
+    fn apply(line: &str, text: &str) -> Pattern<Bag7> {
+        let rule = rule(line).unwrap();
+        rule.check().unwrap();
+        rule.apply(&Pattern::new(text).unwrap())
+    }
+
+    #[test]
+    fn applies_where_it_matches() {
+        let line = "$a$b;$a$c => $a($b;$c)";
+        assert_eq!(apply(line, "TI;TO"), Pattern::new("T(I;O)").unwrap());
+        assert_eq!(apply(line, "TI;OI"), Pattern::new("TI;OI").unwrap());
+    }
+
+    #[test]
+    fn metavariables_stand_for_whole_patterns() {
+        let after = apply("$a$b;$a$c => $a($b;$c)", "[TI]2S;[TI]2Z");
+        let before = Pattern::<Bag7>::new("[TI]2S;[TI]2Z").unwrap();
+        assert_ne!(after, before);
+        assert!(before.equivalent(&after), "{before} vs {after}");
+    }
+
+    #[test]
+    fn check_rejects_wrong_rules() {
+        for line in ["$a;$b => $a", "$a$b => $b$a", "T;I => T"] {
+            assert!(rule(line).unwrap().check().is_err(), "{line}");
+        }
+    }
+
+    #[test]
+    fn malformed_rules() {
+        for (line, error) in [
+            ("$a;$b", "expected `left => right`"),
+            ("$;T => T", "expected a lowercase name after `$`"),
+            ("$a => $a;$b", "$b is only on the right side"),
+            ("($a) => $a$a", "the left side can't be a lone metavariable"),
+        ] {
+            assert_eq!(rule(line).unwrap_err(), error, "{line}");
+        }
+
+        assert!(rule("$a( => $a").is_err());
+    }
+
+    #[test]
+    fn rule_files_point_at_the_bad_line() {
+        let path = std::env::temp_dir().join(format!("rules-{}.txt", std::process::id()));
+        std::fs::write(&path, "# comment\n\n$a$b;$a$c => $a($b;$c)\n$a;$b => $a\n").unwrap();
+        let path = path.to_str().unwrap();
+        let error = super::load::<Bag7>(path).unwrap_err();
+        std::fs::remove_file(path).unwrap();
+        assert!(
+            error.starts_with(&format!("{path}:4: rule doesn't hold")),
+            "{error}"
+        );
+    }
+}
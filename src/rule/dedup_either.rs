@@ -15,8 +15,8 @@ impl<B: Bag> Rule<B> for DedupEither<B> {
                 let left_simplified = self.apply(left);
                 let right_simplified = self.apply(right);
 
-                // if both sides are identical, just return one
-                if left_simplified.to_string() == right_simplified.to_string() {
+                // if both sides are identical (up to how they're written), just return one
+                if left_simplified.canonicalize() == right_simplified.canonicalize() {
                     return left_simplified;
                 }
 
@@ -0,0 +1,76 @@
+use std::marker::PhantomData;
+
+use crate::{bag::Bag, pattern::Pattern, rule::Rule};
+
+pub struct FullAny<B: Bag>(pub PhantomData<B>);
+
+impl<B: Bag> FullAny<B> {
+    // whether this is a set of pieces (or a chain of them) matching every piece exactly once
+    fn full(pattern: &Pattern<B>) -> bool {
+        if !matches!(
+            pattern,
+            Pattern::Any(..) | Pattern::Either(..) | Pattern::Except(..) | Pattern::Group(..)
+        ) {
+            return false;
+        }
+
+        let Some(mut pieces) = pattern.slot() else {
+            return false;
+        };
+        let mut all = B::wildcard();
+        pieces.sort_unstable();
+        all.sort_unstable();
+        pieces == all
+    }
+}
+
+impl<B: Bag> Rule<B> for FullAny<B> {
+    fn name(&self) -> &'static str {
+        "full_any"
+    }
+
+    fn apply(&self, pattern: &Pattern<B>) -> Pattern<B> {
+        match pattern {
+            Pattern::Take(c, n) if Self::full(c) => Pattern::Take(Box::new(Pattern::Wildcard), *n),
+            Pattern::All(c) if Self::full(c) => Pattern::All(Box::new(Pattern::Wildcard)),
+            x if Self::full(x) => Pattern::Wildcard,
+            _ => pattern.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::marker::PhantomData;
+
+    use super::FullAny;
+    use crate::{bag::Bag7, pattern::Pattern, rule::Rule};
+
+    fn apply(text: &str) -> Pattern<Bag7> {
+        let before = Pattern::<Bag7>::new(text).unwrap();
+        let after = FullAny(PhantomData).apply(&before);
+        assert!(before.equivalent(&after), "{before} vs {after}");
+        after
+    }
+
+    #[test]
+    fn every_piece_becomes_a_wildcard() {
+        assert_eq!(apply("[ZSOJLIT]"), Pattern::Wildcard);
+        assert_eq!(apply("T;I;L;J;O;S;Z"), Pattern::Wildcard);
+        assert_eq!(apply("[^]"), Pattern::Wildcard);
+    }
+
+    #[test]
+    fn inside_takes() {
+        assert_eq!(apply("[TILJOSZ]p3"), Pattern::new("*p3").unwrap());
+        assert_eq!(apply("[ZSOJLIT]!"), Pattern::new("*!").unwrap());
+    }
+
+    #[test]
+    fn near_misses_stay() {
+        for text in ["[TILJOSZT]", "[TILJOSZT]p3", "T;I;L;J;O;S;Z;Z", "[TILJOS]"] {
+            let before = Pattern::<Bag7>::new(text).unwrap();
+            assert_eq!(apply(text), before, "{text}");
+        }
+    }
+}
@@ -12,7 +12,7 @@ impl<B: Bag> Rule<B> for JoinAnyAny<B> {
     fn apply(&self, pattern: &Pattern<B>) -> Pattern<B> {
         match pattern {
              Pattern::Either(box Pattern::Any(a), box Pattern::Any(c)) => {
-                return Pattern::Any([a.clone(), c.clone()].concat());
+                Pattern::Any([a.clone(), c.clone()].concat())
             }
             _ => pattern.clone(),
         }
@@ -13,7 +13,7 @@ impl<B: Bag> Rule<B> for JoinSingleAny<B> {
         match pattern {
             Pattern::Either(box Pattern::Single(c), box Pattern::Any(a))
             | Pattern::Either(box Pattern::Any(a), box Pattern::Single(c)) => {
-                return Pattern::Any([vec![Pattern::Single(*c)], a.clone()].concat());
+                Pattern::Any([vec![Pattern::Single(*c)], a.clone()].concat())
             }
             _ => pattern.clone(),
         }
@@ -12,7 +12,7 @@ impl<B: Bag> Rule<B> for JoinSingleSingle<B> {
     fn apply(&self, pattern: &Pattern<B>) -> Pattern<B> {
         match pattern {
             Pattern::Either(box Pattern::Single(a), box Pattern::Single(b)) => {
-                return Pattern::Any(vec![Pattern::Single(*a), Pattern::Single(*b)]);
+                Pattern::Any(vec![Pattern::Single(*a), Pattern::Single(*b)])
             }
             _ => pattern.clone(),
         }
@@ -1,10 +1,14 @@
 pub mod dedup_either; // A;A => A
+pub mod full_any; // [TILJOSZ] => *
 pub mod join_any_any;
 pub mod join_single_any;
 pub mod join_single_single;
+pub mod permutation_family; // TIO;TOI;IOT;ITO;OTI;OIT => [TIO]!
+pub mod push_condition; // *p7{@T=0} => T[ILJOSZ]!
 pub mod shared_prefix; // AB;AC => A(B;C)
 pub mod take_max;
 pub mod unused_group;
+pub mod vacuous_condition; // *p3{#T<=1} => *p3
 pub mod shared_suffix; // (A) => A
 
 
@@ -0,0 +1,159 @@
+use std::{collections::HashSet, marker::PhantomData};
+
+use itertools::Itertools;
+
+use crate::{bag::Bag, pattern::Pattern, queue::Queue, rule::Rule};
+
+pub struct PermutationFamily<B: Bag>(pub PhantomData<B>);
+
+// looking at every queue on every pass gets slow, so leave bigger alternatives alone
+const MAX_QUEUES: usize = 5040;
+
+impl<B: Bag> PermutationFamily<B> {
+    // `[...]k` (or `[...]!`) if these are every ordering of `k` distinct pieces from some set
+    fn family(queues: &[Queue]) -> Option<Pattern<B>> {
+        let k = queues.first()?.len();
+        if k < 2
+            || !queues
+                .iter()
+                .all(|q| q.len() == k && q.vec().iter().all_unique())
+        {
+            return None;
+        }
+
+        let order = B::wildcard();
+        let pieces: Vec<char> = order
+            .iter()
+            .copied()
+            .filter(|c| queues.iter().any(|q| q.vec().contains(c)))
+            .collect();
+        let n = pieces.len();
+        if pieces.len() < k || queues.len() != (n - k + 1..=n).product::<usize>() {
+            return None;
+        }
+
+        let set = Pattern::Any(pieces.into_iter().map(Pattern::Single).collect());
+        Some(if k == n {
+            Pattern::All(Box::new(set))
+        } else {
+            Pattern::Take(Box::new(set), k)
+        })
+    }
+
+    // The distinct prefixes and suffixes of every queue split at `m`, if the queues are exactly
+    // all of the former followed by all of the latter
+    fn split(queues: &[Queue], m: usize) -> Option<(Vec<Queue>, Vec<Queue>)> {
+        let prefixes: Vec<Queue> = queues.iter().map(|q| q.slice(0, m)).unique().collect();
+        let suffixes: Vec<Queue> = queues
+            .iter()
+            .map(|q| q.slice(m, q.len()))
+            .unique()
+            .collect();
+
+        (prefixes.len() * suffixes.len() == queues.len()).then_some((prefixes, suffixes))
+    }
+
+    fn literal(queues: &[Queue]) -> Pattern<B> {
+        match Pattern::find_literal(queues) {
+            Some(p @ Pattern::Either(..)) => Pattern::Group(Box::new(p)),
+            Some(p) => p,
+            None => unreachable!(),
+        }
+    }
+}
+
+impl<B: Bag> Rule<B> for PermutationFamily<B> {
+    fn name(&self) -> &'static str {
+        "permutation_family"
+    }
+
+    fn apply(&self, pattern: &Pattern<B>) -> Pattern<B> {
+        if !matches!(pattern, Pattern::Either(..) | Pattern::Any(..))
+            || pattern.count() > MAX_QUEUES
+        {
+            return pattern.clone();
+        }
+
+        let queues = pattern.queues();
+        let len = match queues.first() {
+            Some(q) => q.len(),
+            None => return pattern.clone(),
+        };
+        if queues.iter().any(|q| q.len() != len)
+            || queues.iter().collect::<HashSet<_>>().len() != queues.len()
+        {
+            return pattern.clone();
+        }
+
+        if let Some(p) = Self::family(&queues) {
+            return p;
+        }
+
+        // the family may only be one factor of a sequence, at the front or at the back
+        for m in (1..len).rev() {
+            if let Some((prefixes, suffixes)) = Self::split(&queues, m) {
+                if let Some(p) = Self::family(&prefixes) {
+                    return Pattern::Seq(Box::new(p), Box::new(Self::literal(&suffixes)));
+                }
+
+                if let Some(p) = Self::family(&suffixes) {
+                    return Pattern::Seq(Box::new(Self::literal(&prefixes)), Box::new(p));
+                }
+            }
+        }
+
+        pattern.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::marker::PhantomData;
+
+    use super::PermutationFamily;
+    use crate::{bag::Bag7, pattern::Pattern, rule::Rule};
+
+    // applies the rule to `text`, checking that it was rewritten without changing its queues
+    fn rewritten(text: &str) -> Pattern<Bag7> {
+        let before = Pattern::<Bag7>::new(text).unwrap();
+        let after = PermutationFamily(PhantomData).apply(&before);
+        assert_ne!(after, before, "{text} wasn't rewritten");
+        assert!(before.equivalent(&after), "{before} vs {after}");
+        after
+    }
+
+    #[test]
+    fn every_ordering_becomes_an_all() {
+        let after = rewritten("TIO;TOI;ITO;IOT;OTI;OIT");
+        assert_eq!(after, Pattern::<Bag7>::new("[TIO]!").unwrap());
+    }
+
+    #[test]
+    fn some_orderings_become_a_take() {
+        let after = rewritten("TI;TO;IT;IO;OT;OI");
+        assert_eq!(after, Pattern::<Bag7>::new("[TIO]p2").unwrap());
+    }
+
+    #[test]
+    fn families_inside_sequences() {
+        for text in [
+            "TIOS;TOIS;ITOS;IOTS;OTIS;OITS",
+            "STI;STO;SIT;SIO;SOT;SOI",
+            "STI;SIT;ZTI;ZIT",
+        ] {
+            rewritten(text);
+        }
+    }
+
+    #[test]
+    fn leaves_other_families_alone() {
+        for text in ["TI;IT;TO", "TT;TI;IT;II", "TIO;TOI;ITO"] {
+            let before = Pattern::<Bag7>::new(text).unwrap();
+            assert_eq!(
+                PermutationFamily(PhantomData).apply(&before),
+                before,
+                "{text}"
+            );
+        }
+    }
+}
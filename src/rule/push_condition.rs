@@ -0,0 +1,214 @@
+use std::marker::PhantomData;
+
+use itertools::Itertools;
+
+use crate::{
+    bag::Bag,
+    condition::{Condition, Place},
+    pattern::Pattern,
+    rule::Rule,
+};
+
+pub struct PushCondition<B: Bag>(pub PhantomData<B>);
+
+impl<B: Bag> PushCondition<B> {
+    // The distinct pieces drawn from and how many, for `[...]pN` and `[...]!`
+    fn draw(base: &Pattern<B>) -> Option<(Vec<char>, usize)> {
+        let (c, n) = match base {
+            Pattern::Group(p) => return Self::draw(p),
+            Pattern::Take(c, n) => (c, *n),
+            Pattern::All(c) => (c, c.slot()?.len()),
+            _ => return None,
+        };
+        let pieces = c.slot()?;
+        if !pieces.iter().all_unique() || n == 0 || n > pieces.len() {
+            return None;
+        }
+
+        Some((pieces, n))
+    }
+
+    // `n` distinct draws from `pieces`, written as briefly as possible
+    fn take(pieces: &[char], n: usize) -> Option<Pattern<B>> {
+        let set = match pieces {
+            [] => return None,
+            [p] => Pattern::Single(*p),
+            _ => Pattern::Any(pieces.iter().map(|&p| Pattern::Single(p)).collect()),
+        };
+
+        Some(if n == 1 {
+            set
+        } else if n == pieces.len() {
+            Pattern::All(Box::new(set))
+        } else {
+            Pattern::Take(Box::new(set), n)
+        })
+    }
+
+    // The same queues as `base{condition}`, with no condition, if it is simple enough
+    fn push(pieces: &[char], n: usize, condition: &Condition<B>) -> Option<Pattern<B>> {
+        match condition {
+            Condition::Group(c) => Self::push(pieces, n, c),
+            // none of some pieces: leave them out of the draw
+            Condition::Count(a, k) if k.test(0) && (1..=k.max() + 1).all(|x| !k.test(x)) => {
+                let a = a.slot()?;
+                let rest: Vec<char> = pieces.iter().copied().filter(|p| !a.contains(p)).collect();
+                if rest.len() == pieces.len() || rest.len() < n {
+                    return None;
+                }
+
+                Self::take(&rest, n)
+            }
+            // one of some pieces first (or last): that piece, then the rest drawn without it
+            Condition::Location(a, places) => {
+                let [place @ Place::At(_)] = places.as_slice() else {
+                    return None;
+                };
+                let first = place.contains(0, n);
+                if !first && !place.contains(n - 1, n) {
+                    return None;
+                }
+
+                let a = a.slot()?;
+                let alternatives: Vec<Pattern<B>> = pieces
+                    .iter()
+                    .filter(|p| a.contains(p))
+                    .map(|&p| {
+                        let rest: Vec<char> = pieces.iter().copied().filter(|&x| x != p).collect();
+                        let p = Pattern::Single(p);
+                        match Self::take(&rest, n - 1) {
+                            Some(_) if n == 1 => p,
+                            Some(rest) if first => Pattern::Seq(Box::new(p), Box::new(rest)),
+                            Some(rest) => Pattern::Seq(Box::new(rest), Box::new(p)),
+                            None => p,
+                        }
+                    })
+                    .collect();
+
+                let either = alternatives
+                    .into_iter()
+                    .reduce(|a, b| Pattern::Either(Box::new(a), Box::new(b)))?;
+
+                Some(match either {
+                    x @ (Pattern::Seq(..) | Pattern::Either(..)) => Pattern::Group(Box::new(x)),
+                    x => x,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl<B: Bag> Rule<B> for PushCondition<B> {
+    fn name(&self) -> &'static str {
+        "push_condition"
+    }
+
+    fn apply(&self, pattern: &Pattern<B>) -> Pattern<B> {
+        let Pattern::Condition(base, condition) = pattern else {
+            return pattern.clone();
+        };
+        let Some((pieces, n)) = Self::draw(base) else {
+            return pattern.clone();
+        };
+
+        // push the first part of a chain of `&` that can be, and keep filtering by the others
+        let mut terms = vec![];
+        condition.terms(true, &mut terms);
+        for (i, term) in terms.iter().enumerate() {
+            let Some(pushed) = Self::push(&pieces, n, term) else {
+                continue;
+            };
+
+            let rest = terms
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(_, x)| (*x).clone())
+                .reduce(|a, b| Condition::And(Box::new(a), Box::new(b)));
+
+            return match rest {
+                Some(c) => Pattern::Condition(Box::new(pushed), Box::new(c.canonicalize())),
+                None => pushed,
+            };
+        }
+
+        pattern.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{bag::Bag7, pattern::Pattern};
+
+    // simplifies `text`, then reads the printed result back in, the way the cli hands it around
+    fn round_trip(text: &str) -> (Pattern<Bag7>, Pattern<Bag7>) {
+        let before = Pattern::<Bag7>::new(text).unwrap();
+        let printed = before.simplify().to_string();
+        let after = Pattern::<Bag7>::new(&printed).unwrap();
+        (before, after)
+    }
+
+    #[test]
+    fn leftover_condition_stays_on_the_whole_either() {
+        let (before, after) = round_trip("*p4{@[TI]=0&S<Z}");
+        assert!(before.equivalent(&after), "{before} vs {after}");
+    }
+
+    #[test]
+    fn leftover_condition_stays_on_the_whole_seq() {
+        let (before, after) = round_trip("*p7{@T=-1&S<Z}");
+        assert!(before.equivalent(&after), "{before} vs {after}");
+    }
+
+    // pushes one condition into `text`, and compares against expanding it then filtering
+    fn pushed(text: &str) -> Pattern<Bag7> {
+        use crate::rule::{Rule, push_condition::PushCondition};
+
+        let before = Pattern::<Bag7>::new(text).unwrap();
+        let after = PushCondition(std::marker::PhantomData).apply(&before);
+        assert_ne!(after, before, "{text} wasn't rewritten");
+
+        let Pattern::Condition(base, condition) = &before else {
+            unreachable!()
+        };
+        let mut filtered: Vec<_> = base.iter_queues().filter(|q| condition.has(q)).collect();
+        let mut rewritten: Vec<_> = after.queues();
+        filtered.sort();
+        rewritten.sort();
+        assert_eq!(rewritten, filtered, "{text} => {after}");
+        after
+    }
+
+    #[test]
+    fn pushes_places() {
+        for text in ["*p4{@T=0}", "*p4{@[TI]=-1}", "[TIO]!{@O=2}", "*p3{@T=-3}"] {
+            pushed(text);
+        }
+    }
+
+    #[test]
+    fn pushes_missing_pieces() {
+        for text in ["*p4{#I=0}", "[TIOS]p2{#[TI]<1}", "*p3{#O=0..1}"] {
+            pushed(text);
+        }
+    }
+
+    #[test]
+    fn keeps_leftover_conditions() {
+        for text in ["*p4{@T=0&S<Z}", "*p4{#I=0&@O=-1..}", "*p3{@[TI]=0&#O=1}"] {
+            assert!(matches!(pushed(text), Pattern::Condition(..)), "{text}");
+        }
+    }
+
+    #[test]
+    fn leaves_ranges_alone() {
+        use crate::rule::{Rule, push_condition::PushCondition};
+
+        let before = Pattern::<Bag7>::new("*p4{@T=1..3}").unwrap();
+        assert_eq!(
+            PushCondition(std::marker::PhantomData).apply(&before),
+            before
+        );
+    }
+}
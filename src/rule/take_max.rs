@@ -16,9 +16,35 @@ impl<B: Bag> Rule<B> for TakeMax<B> {
                     return Pattern::All(c.clone());
                 }
 
-                return *c.clone()
+                pattern.clone()
             },
             _ => pattern.clone(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::marker::PhantomData;
+
+    use super::TakeMax;
+    use crate::{bag::Bag7, pattern::Pattern, rule::Rule};
+
+    fn apply(text: &str) -> Pattern<Bag7> {
+        TakeMax(PhantomData).apply(&Pattern::<Bag7>::new(text).unwrap())
+    }
+
+    #[test]
+    fn takes_of_everything_become_alls() {
+        assert_eq!(apply("[TIO]p3"), Pattern::<Bag7>::new("[TIO]!").unwrap());
+    }
+
+    #[test]
+    fn partial_takes_keep_their_count() {
+        for text in ["[TIO]p2", "*p3", "[TTI]p2"] {
+            let before = Pattern::<Bag7>::new(text).unwrap();
+            let after = apply(text);
+            assert!(before.equivalent(&after), "{before} vs {after}");
+        }
+    }
+}
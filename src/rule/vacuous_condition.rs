@@ -0,0 +1,114 @@
+use std::marker::PhantomData;
+
+use crate::{bag::Bag, condition::Condition, count::Matcher, pattern::Pattern, rule::Rule};
+
+pub struct VacuousCondition<B: Bag>(pub PhantomData<B>);
+
+// checking a condition means going through every queue of its base, so skip huge ones
+const MAX_QUEUES: usize = 100_000;
+
+impl<B: Bag> VacuousCondition<B> {
+    // How many of the base's queues the condition holds for, out of how many there are
+    fn tally(base: &Pattern<B>, condition: &Condition<B>) -> (usize, usize) {
+        let matcher = Matcher::new(condition);
+        let mut hits = 0;
+        let mut total = 0;
+        for q in base.iter_queues() {
+            total += 1;
+            let has = match &matcher {
+                Some(m) => m.has(&q),
+                None => condition.has(&q),
+            };
+            if has {
+                hits += 1;
+            }
+        }
+
+        (hits, total)
+    }
+}
+
+impl<B: Bag> Rule<B> for VacuousCondition<B> {
+    fn name(&self) -> &'static str {
+        "vacuous_condition"
+    }
+
+    fn apply(&self, pattern: &Pattern<B>) -> Pattern<B> {
+        let Pattern::Condition(base, condition) = pattern else {
+            return pattern.clone();
+        };
+        if base.count() > MAX_QUEUES {
+            return pattern.clone();
+        }
+
+        // a condition that never holds leaves nothing, and one that always does changes nothing
+        match Self::tally(base, condition) {
+            (0, _) => return Pattern::empty(),
+            (hits, total) if hits == total => return *base.clone(),
+            _ => {}
+        }
+
+        // the same goes for each part of a chain of `&`
+        let mut terms = vec![];
+        condition.terms(true, &mut terms);
+        let kept: Vec<&Condition<B>> = terms
+            .into_iter()
+            .filter(|x| {
+                let (hits, total) = Self::tally(base, x);
+                hits != total
+            })
+            .collect();
+
+        match kept.split_first() {
+            Some((first, rest)) if !rest.is_empty() || *first != &**condition => {
+                let c = rest.iter().fold((*first).clone(), |a, b| {
+                    Condition::And(Box::new(a), Box::new((*b).clone()))
+                });
+                Pattern::Condition(base.clone(), Box::new(c.canonicalize()))
+            }
+            _ => pattern.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::marker::PhantomData;
+
+    use super::VacuousCondition;
+    use crate::{bag::Bag7, pattern::Pattern, rule::Rule};
+
+    // applies the rule to `before`, checking that it keeps the queues and gives `after`
+    fn rewrites(before: &str, after: &str) {
+        let p = Pattern::<Bag7>::new(before).unwrap();
+        let q = VacuousCondition(PhantomData).apply(&p);
+        assert!(p.equivalent(&q), "{p} vs {q}");
+        assert_eq!(q, Pattern::new(after).unwrap(), "{before}");
+    }
+
+    #[test]
+    fn contradictions_leave_nothing() {
+        let p = Pattern::<Bag7>::new("[TI]!{#O=1}").unwrap();
+        let q = VacuousCondition(PhantomData).apply(&p);
+        assert_eq!(q, Pattern::empty());
+        assert!(p.equivalent(&q));
+    }
+
+    #[test]
+    fn conditions_that_always_hold_go() {
+        rewrites("[TI]!{#T=1}", "[TI]!");
+        rewrites("*p3{!#T=2}", "*p3");
+    }
+
+    #[test]
+    fn parts_of_chains_that_always_hold_go() {
+        rewrites("[TIO]!{#T=1&T<I}", "[TIO]!{T<I}");
+        rewrites("*p3{#T<=1&O<S&#I<2}", "*p3{O<S}");
+    }
+
+    #[test]
+    fn leaves_other_conditions_alone() {
+        rewrites("[TIO]!{T<I}", "[TIO]!{T<I}");
+        rewrites("*p3{#T=1|I<O}", "*p3{#T=1|I<O}");
+    }
+}
@@ -0,0 +1,230 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashSet},
+    str::FromStr,
+};
+
+use crate::{bag::Bag, pattern::Pattern, rule::Rule};
+
+// how many patterns to rewrite before settling for the best one seen
+const MAX_EXPANSIONS: usize = 2000;
+
+// How `simplify` goes about applying rules
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Strategy {
+    // the first rule that changes anything, over and over
+    Greedy,
+    // every rule everywhere, best candidate first, keeping the cheapest result
+    Saturate,
+}
+
+// What the saturating simplifier minimizes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cost {
+    Size,
+    Length,
+}
+
+impl FromStr for Strategy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "greedy" => Ok(Self::Greedy),
+            "saturate" => Ok(Self::Saturate),
+            _ => Err(format!(
+                "unknown strategy {s:?}, expected `greedy` or `saturate`"
+            )),
+        }
+    }
+}
+
+impl FromStr for Cost {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "size" => Ok(Self::Size),
+            "length" => Ok(Self::Length),
+            _ => Err(format!("unknown cost {s:?}, expected `size` or `length`")),
+        }
+    }
+}
+
+impl<B> Pattern<B>
+where
+    B: Bag,
+{
+    pub fn cost(&self, cost: Cost) -> usize {
+        match cost {
+            Cost::Size => self.size(),
+            Cost::Length => self.to_string().chars().count(),
+        }
+    }
+
+    // Best-first search over every way of applying the rules (and of reassociating and
+    // reordering `;` and sequences) anywhere in the pattern, returning the cheapest pattern it
+    // comes across. Costs are taken in canonical form, so groups only count where needed
+    pub fn simplify_saturate(&self, cost: Cost, verify: bool) -> Self {
+        let rules = Self::rules();
+        let measure = |p: &Self| p.canonicalize().cost(cost);
+        let mut seen = HashSet::from([format!("{self:?}")]);
+        let mut found = vec![self.clone()];
+        let mut frontier = BinaryHeap::from([Reverse((measure(self), 0))]);
+        let mut best = (measure(self), self.canonicalize().to_string(), 0);
+
+        let mut expanded = 0;
+        while let Some(Reverse((_, i))) = frontier.pop() {
+            if expanded >= MAX_EXPANSIONS {
+                break;
+            }
+
+            expanded += 1;
+            for next in found[i].rewrites(&rules) {
+                // a rewrite has to keep the queues of the whole pattern, not just of the part it
+                // changed, which can sit inside a take
+                if !seen.insert(format!("{next:?}"))
+                    || (verify && !Self::verify_rewrite("saturate", self, &next))
+                {
+                    continue;
+                }
+
+                let c = measure(&next);
+                let key = next.canonicalize().to_string();
+                if (c, &key) < (best.0, &best.1) {
+                    best = (c, key, found.len());
+                }
+
+                frontier.push(Reverse((c, found.len())));
+                found.push(next);
+            }
+        }
+
+        found.swap_remove(best.2).canonicalize()
+    }
+
+    // Every pattern one rule application away from this one, at the top or anywhere inside
+    fn rewrites(&self, rules: &[Box<dyn Rule<B>>]) -> Vec<Self> {
+        let mut v = vec![];
+        for rule in rules {
+            let next = rule.apply(self);
+            if next != *self {
+                v.push(next);
+            }
+        }
+
+        // `;` and sequences are associative, and `;` doesn't care about order as far as the
+        // matched queues go, so try those shapes too for the rules to work with
+        match self {
+            Self::Either(a, b) => {
+                v.push(Self::Either(b.clone(), a.clone()));
+                if let Self::Either(x, y) = &**a {
+                    v.push(Self::Either(
+                        x.clone(),
+                        Box::new(Self::Either(y.clone(), b.clone())),
+                    ));
+                }
+
+                if let Self::Either(x, y) = &**b {
+                    v.push(Self::Either(
+                        Box::new(Self::Either(a.clone(), x.clone())),
+                        y.clone(),
+                    ));
+                }
+            }
+            Self::Seq(a, b) => {
+                if let Self::Seq(x, y) = &**a {
+                    v.push(Self::Seq(
+                        x.clone(),
+                        Box::new(Self::Seq(y.clone(), b.clone())),
+                    ));
+                }
+
+                if let Self::Seq(x, y) = &**b {
+                    v.push(Self::Seq(
+                        Box::new(Self::Seq(a.clone(), x.clone())),
+                        y.clone(),
+                    ));
+                }
+            }
+            _ => {}
+        }
+
+        let inner = |p: &Self| p.rewrites(rules);
+        match self {
+            Self::Single(..) | Self::Wildcard => {}
+            Self::Either(a, b) | Self::Seq(a, b) => {
+                let join = |x: Self, y: Self| match self {
+                    Self::Either(..) => Self::Either(Box::new(x), Box::new(y)),
+                    _ => Self::Seq(Box::new(x), Box::new(y)),
+                };
+                v.extend(inner(a).into_iter().map(|x| join(x, *b.clone())));
+                v.extend(inner(b).into_iter().map(|x| join(*a.clone(), x)));
+            }
+            Self::Any(t) | Self::Except(t) => {
+                for (i, member) in t.iter().enumerate() {
+                    for x in inner(member) {
+                        let mut t = t.clone();
+                        t[i] = x;
+                        v.push(match self {
+                            Self::Any(..) => Self::Any(t),
+                            _ => Self::Except(t),
+                        });
+                    }
+                }
+            }
+            Self::Group(c) => v.extend(inner(c).into_iter().map(|x| Self::Group(Box::new(x)))),
+            Self::Take(c, n) => {
+                v.extend(inner(c).into_iter().map(|x| Self::Take(Box::new(x), *n)));
+            }
+            Self::All(c) => v.extend(inner(c).into_iter().map(|x| Self::All(Box::new(x)))),
+            Self::Condition(p, c) => v.extend(
+                inner(p)
+                    .into_iter()
+                    .map(|x| Self::Condition(Box::new(x), c.clone())),
+            ),
+            Self::Unique(c) => v.extend(inner(c).into_iter().map(|x| Self::Unique(Box::new(x)))),
+        }
+
+        v
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cost;
+    use crate::{bag::Bag7, pattern::Pattern, simplify::Settings};
+
+    const PATTERNS: [&str; 5] = [
+        "TI;TO;SI;SO",
+        "TIO;TOI;ITO;IOT;OTI;OIT",
+        "T(I;O);T(S;Z)",
+        "*p2{#T=0}",
+        "(T;T)2",
+    ];
+
+    #[test]
+    fn keeps_queues_and_beats_greedy() {
+        for cost in [Cost::Size, Cost::Length] {
+            for text in PATTERNS {
+                let p = Pattern::<Bag7>::new(text).unwrap();
+                let saturated = p.simplify_saturate(cost, true);
+                let greedy = p.simplify_with(Settings {
+                    verify: true,
+                    ..Settings::default()
+                });
+                assert!(p.equivalent(&saturated), "{text} vs {saturated}");
+                assert!(
+                    saturated.cost(cost) <= greedy.canonicalize().cost(cost),
+                    "{text}: {saturated} vs {greedy}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn factors_out_shared_pieces() {
+        for cost in [Cost::Size, Cost::Length] {
+            let p = Pattern::<Bag7>::new("TI;TO;SI;SO").unwrap();
+            assert_eq!(p.simplify_saturate(cost, false).to_string(), "[TS][IO]");
+        }
+    }
+}
@@ -1,36 +1,94 @@
-use std::marker::PhantomData;
+use std::{cell::Cell, marker::PhantomData, time::Instant};
 
 use crate::{
     bag::Bag,
+    equiv::Witness,
     pattern::Pattern,
+    rewrite,
     rule::{
-        Rule, dedup_either::DedupEither, join_any_any::JoinAnyAny, join_single_any::JoinSingleAny,
-        join_single_single::JoinSingleSingle, shared_prefix::SharedPrefix,
+        Rule, dedup_either::DedupEither, full_any::FullAny, join_any_any::JoinAnyAny,
+        join_single_any::JoinSingleAny, join_single_single::JoinSingleSingle,
+        permutation_family::PermutationFamily, push_condition::PushCondition,
+        shared_prefix::SharedPrefix,
         shared_suffix::SharedSuffix, take_max::TakeMax, unused_group::UnusedGroup,
+        vacuous_condition::VacuousCondition,
     },
 };
 
+// How the greedy simplifier goes about its rewrites
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Settings<'a> {
+    // check every rewrite against the queues the whole pattern matched before it, and throw it
+    // out (with a note on stderr) if it changes them
+    pub verify: bool,
+    // print each rewrite as it happens
+    pub trace: bool,
+    // stop rewriting (keeping what there is so far) once this passes
+    pub deadline: Option<Instant>,
+    // the same, once this many rules have been tried, counting down as they are
+    pub attempts: Option<&'a Cell<usize>>,
+}
+
+impl Settings<'_> {
+    fn expired(&self) -> bool {
+        self.deadline.is_some_and(|x| Instant::now() >= x)
+            || self.attempts.is_some_and(|x| x.get() == 0)
+    }
+
+    fn attempt(&self) {
+        if let Some(x) = self.attempts {
+            x.set(x.get().saturating_sub(1));
+        }
+    }
+}
+
 impl<B> Pattern<B>
 where
     B: Bag,
 {
     pub fn rules() -> Vec<Box<dyn Rule<B>>> {
-        vec![
+        let mut rules: Vec<Box<dyn Rule<B>>> = vec![
+            Box::new(PermutationFamily(PhantomData)),
             Box::new(SharedPrefix(PhantomData)),
             Box::new(SharedSuffix(PhantomData)),
             Box::new(DedupEither(PhantomData)),
+            Box::new(FullAny(PhantomData)),
             Box::new(JoinAnyAny(PhantomData)),
             Box::new(JoinSingleAny(PhantomData)),
             Box::new(JoinSingleSingle(PhantomData)),
             Box::new(TakeMax(PhantomData)),
             Box::new(UnusedGroup(PhantomData)),
-        ]
+            Box::new(VacuousCondition(PhantomData)),
+            Box::new(PushCondition(PhantomData)),
+        ];
+
+        // anything loaded with `simplify --rules` comes after the built-in rules
+        rules.extend(rewrite::user_rules());
+        rules
     }
 
     pub fn simplify(&self) -> Self {
+        self.simplify_with(Settings {
+            trace: true,
+            ..Settings::default()
+        })
+    }
+
+    pub fn simplify_with(&self, settings: Settings) -> Self {
+        self.simplify_in(settings, &|p| p.clone())
+    }
+
+    pub fn simplify_one(&self, settings: Settings) -> Self {
+        self.simplify_one_in(settings, &|p| p.clone())
+    }
+
+    // Simplifies this part of a bigger pattern, which `whole` rebuilds around a replacement for
+    // it. A rewrite can keep the queues of the part but not those of the whole, like `T;T` to
+    // `T` inside `(T;T)2`, so that's what gets verified
+    fn simplify_in(&self, settings: Settings, whole: &dyn Fn(&Self) -> Self) -> Self {
         let mut current = self.clone();
-        loop {
-            let next = current.simplify_one();
+        while !settings.expired() {
+            let next = current.simplify_one_in(settings, whole);
             // println!("{current} -> {next}");
             if next == current {
                 break;
@@ -41,38 +99,112 @@ where
 
         current
     }
-    pub fn simplify_one(&self) -> Self {
+
+    fn simplify_one_in(&self, settings: Settings, whole: &dyn Fn(&Self) -> Self) -> Self {
         let post = match self.clone() {
             Self::Single(c) => Self::Single(c),
             Self::Wildcard => Self::Wildcard,
-            Self::Either(box a, box b) => {
-                Self::Either(Box::new(a.simplify()), Box::new(b.simplify()))
+            Self::Either(a, b) => {
+                let a = a.simplify_in(settings, &|x| {
+                    whole(&Self::Either(Box::new(x.clone()), b.clone()))
+                });
+                let b = b.simplify_in(settings, &|x| {
+                    whole(&Self::Either(Box::new(a.clone()), Box::new(x.clone())))
+                });
+                Self::Either(Box::new(a), Box::new(b))
+            }
+            Self::Seq(a, b) => {
+                let a = a.simplify_in(settings, &|x| {
+                    whole(&Self::Seq(Box::new(x.clone()), b.clone()))
+                });
+                let b = b.simplify_in(settings, &|x| {
+                    whole(&Self::Seq(Box::new(a.clone()), Box::new(x.clone())))
+                });
+                Self::Seq(Box::new(a), Box::new(b))
+            }
+            Self::Any(mut t) | Self::Except(mut t) => {
+                let any = matches!(self, Self::Any(..));
+                for i in 0..t.len() {
+                    let x = t[i].simplify_in(settings, &|x| {
+                        let mut t = t.clone();
+                        t[i] = x.clone();
+                        whole(&if any { Self::Any(t) } else { Self::Except(t) })
+                    });
+                    t[i] = x;
+                }
+
+                if any { Self::Any(t) } else { Self::Except(t) }
+            }
+            Self::Group(c) => {
+                Self::Group(Box::new(c.simplify_in(settings, &|x| {
+                    whole(&Self::Group(Box::new(x.clone())))
+                })))
+            }
+            Self::Take(i, n) => Self::Take(
+                Box::new(i.simplify_in(settings, &|x| whole(&Self::Take(Box::new(x.clone()), n)))),
+                n,
+            ),
+            Self::All(i) => {
+                Self::All(Box::new(i.simplify_in(settings, &|x| {
+                    whole(&Self::All(Box::new(x.clone())))
+                })))
+            }
+            Self::Condition(i, c) => {
+                let i = Box::new(i.simplify_in(settings, &|x| {
+                    whole(&Self::Condition(Box::new(x.clone()), c.clone()))
+                }));
+                // the rewrites inside the condition get checked all together
+                let simplified = Box::new(c.simplify(settings));
+                let rejected = settings.verify
+                    && simplified != c
+                    && !Self::verify_rewrite(
+                        "simplify_condition",
+                        &whole(&Self::Condition(i.clone(), c.clone())),
+                        &whole(&Self::Condition(i.clone(), simplified.clone())),
+                    );
+                Self::Condition(i, if rejected { c } else { simplified })
+            }
+            Self::Unique(c) => {
+                Self::Unique(Box::new(c.simplify_in(settings, &|x| {
+                    whole(&Self::Unique(Box::new(x.clone())))
+                })))
             }
-            Self::Seq(box a, box b) => Self::Seq(Box::new(a.simplify()), Box::new(b.simplify())),
-            Self::Any(patterns) => Self::Any(patterns.iter().map(|p| p.simplify()).collect()),
-            Self::Group(box c) => Self::Group(Box::new(c.simplify())),
-            Self::Take(box i, c) => Self::Take(Box::new(i.simplify()), c),
-            Self::All(box i) => Self::All(Box::new(i)),
-            Self::Condition(box i, c) => Self::Condition(Box::new(i.simplify()), c),
-            Self::Unique(c) => Self::Unique(Box::new(c.simplify())),
         };
 
-        Self::apply_all_rules(&post)
+        Self::apply_all_rules(&post, settings, whole)
     }
 
-    fn apply_all_rules(pattern: &Self) -> Self {
+    fn apply_all_rules(pattern: &Self, settings: Settings, whole: &dyn Fn(&Self) -> Self) -> Self {
         let mut current = pattern.clone();
         let mut changed = true;
 
-        while changed {
+        while changed && !settings.expired() {
             changed = false;
 
             for rule in Self::rules().iter() {
+                if settings.expired() {
+                    break;
+                }
+
+                settings.attempt();
                 let new_pattern = rule.apply(&current);
 
                 // only accept the change if it maintains correctness
                 if new_pattern != current {
-                    println!("\x1b[36m{}\x1b[0m {current} -> {new_pattern}", rule.name());
+                    if settings.verify
+                        && !Self::verify_rewrite(
+                            rule.name(),
+                            &whole(&current),
+                            &whole(&new_pattern),
+                        )
+                    {
+                        continue;
+                    }
+
+                    if settings.trace {
+                        println!("\x1b[36m{}\x1b[0m {current} -> {new_pattern}", rule.name());
+                    }
+
                     current = new_pattern;
                     changed = true;
                     break; // restart with all rules on the new pattern
@@ -82,4 +214,57 @@ where
 
         current
     }
+
+    // Whether a rewrite keeps the matched queues the same, complaining on stderr if not
+    pub fn verify_rewrite(name: &str, before: &Self, after: &Self) -> bool {
+        let Some(d) = before.difference(after, false) else {
+            return true;
+        };
+
+        let side = |w: Option<Witness>| w.map_or("none".to_string(), |x| format!("{:?}", x.queue));
+        eprintln!(
+            "\x1b[31mrejected {name}\x1b[0m {before} -> {after}: lost {}, gained {}",
+            side(d.left),
+            side(d.right),
+        );
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Settings;
+    use crate::{bag::Bag7, pattern::Pattern};
+
+    fn verified(text: &str) -> Pattern<Bag7> {
+        let before = Pattern::<Bag7>::new(text).unwrap();
+        let after = before.simplify_with(Settings {
+            verify: true,
+            ..Settings::default()
+        });
+        assert!(before.equivalent(&after), "{before} vs {after}");
+        after
+    }
+
+    #[test]
+    fn verifies_against_the_whole_pattern() {
+        verified("(T;T)2");
+        verified("[(T;T)I]!");
+    }
+
+    #[test]
+    fn verifies_conditions() {
+        verified("(TTI){#[(TT)T]=1}");
+        verified("*p4{@[TI]=0&S<Z}");
+    }
+
+    #[test]
+    fn sound_rewrites_all_go_through() {
+        for text in ["TI;TO;SI;SO", "[TIO]2;T", "*p3{#I=0}"] {
+            let quiet = Pattern::<Bag7>::new(text)
+                .unwrap()
+                .simplify_with(Settings::default());
+            assert_eq!(verified(text), quiet, "{text}");
+        }
+    }
 }
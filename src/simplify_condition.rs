@@ -0,0 +1,137 @@
+use crate::{bag::Bag, condition::Condition, simplify::Settings};
+
+impl<B> Condition<B>
+where
+    B: Bag,
+{
+    // Simplifies every pattern inside this condition, then drops repeats (`A&A`), absorbed
+    // terms (`A|(A&B)`, `A&(A|B)`) and double negations
+    pub fn simplify(&self, settings: Settings) -> Self {
+        let simplified = match self {
+            Self::Group(c) => c.simplify(settings),
+            Self::Before(a, b) => Self::Before(a.simplify_with(settings), b.simplify_with(settings)),
+            Self::After(a, b) => Self::After(a.simplify_with(settings), b.simplify_with(settings)),
+            // rewriting can reorder queues, which only counting queues of one length ignores
+            Self::Count(a, n) if a.fixed_len().is_some() => {
+                Self::Count(a.simplify_with(settings), *n)
+            }
+            Self::Count(a, n) => Self::Count(a.clone(), *n),
+            Self::Location(a, n) => Self::Location(a.simplify_with(settings), n.clone()),
+            Self::Not(c) => match c.simplify(settings) {
+                Self::Not(x) => *x,
+                x => Self::Not(Box::new(x)),
+            },
+            Self::And(..) | Self::Or(..) => {
+                let and = matches!(self, Self::And(..));
+                let mut terms = vec![];
+                self.terms(and, &mut terms);
+                let terms: Vec<Self> = terms.iter().map(|x| x.simplify(settings)).collect();
+
+                // a term holding every part of another term of the chain (the other way around,
+                // like `A&B` next to `A` in `A|(A&B)`) adds nothing
+                let parts: Vec<Vec<&Self>> = terms
+                    .iter()
+                    .map(|x| {
+                        let mut v = vec![];
+                        x.terms(!and, &mut v);
+                        v
+                    })
+                    .collect();
+                let absorbed = |i: usize| {
+                    (0..parts.len()).any(|j| {
+                        j != i
+                            && parts[j].iter().all(|x| parts[i].contains(x))
+                            && (parts[j].len() < parts[i].len() || j < i)
+                    })
+                };
+                let v: Vec<Self> = (0..terms.len())
+                    .filter(|&i| !absorbed(i))
+                    .map(|i| terms[i].clone())
+                    .collect();
+
+                let join = |a: Self, b: Self| {
+                    if and {
+                        Self::And(Box::new(a), Box::new(b))
+                    } else {
+                        Self::Or(Box::new(a), Box::new(b))
+                    }
+                };
+                let mut v = v.into_iter();
+                let first = v.next().expect("a chain has at least one term");
+                v.fold(first, join)
+            }
+            Self::Phantom(p) => Self::Phantom(*p),
+        };
+
+        // put back only the groups that printing needs
+        simplified.canonicalize()
+    }
+
+    // The operands of a chain of `&` (or `|`), looking through groups
+    pub fn terms<'a>(&'a self, and: bool, terms: &mut Vec<&'a Self>) {
+        match self {
+            Self::Group(c) => c.terms(and, terms),
+            Self::And(a, b) if and => {
+                a.terms(and, terms);
+                b.terms(and, terms);
+            }
+            Self::Or(a, b) if !and => {
+                a.terms(and, terms);
+                b.terms(and, terms);
+            }
+            x => terms.push(x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{bag::Bag7, pattern::Pattern, simplify::Settings};
+
+    // simplifies just the condition of `text`, checking that its queues stay the same
+    fn simplified(text: &str) -> Pattern<Bag7> {
+        let before = Pattern::<Bag7>::new(text).unwrap();
+        let Pattern::Condition(base, condition) = &before else {
+            panic!("{text} is not a condition");
+        };
+        let after = Pattern::Condition(
+            base.clone(),
+            Box::new(condition.simplify(Settings::default())),
+        );
+        assert!(before.equivalent(&after), "{before} vs {after}");
+        after
+    }
+
+    fn same(a: &str, b: &str) {
+        assert_eq!(simplified(a), Pattern::<Bag7>::new(b).unwrap(), "{a}");
+    }
+
+    #[test]
+    fn drops_repeats() {
+        same("*p3{T<I&T<I}", "*p3{T<I}");
+        same("*p3{#O=1|#O=1|S<Z}", "*p3{#O=1|S<Z}");
+    }
+
+    #[test]
+    fn drops_absorbed_terms() {
+        same("*p3{T<I|(T<I&O<S)}", "*p3{T<I}");
+        same("*p3{(O<S|T<I)&T<I}", "*p3{T<I}");
+    }
+
+    #[test]
+    fn drops_double_negations() {
+        same("*p3{!!T<I}", "*p3{T<I}");
+    }
+
+    #[test]
+    fn keeps_the_order_of_counted_sets() {
+        same("(TTI){#[(TT)T]=1}", "(TTI){#[(TT)T]=1}");
+    }
+
+    #[test]
+    fn simplify_keeps_counted_sets_working() {
+        let before = Pattern::<Bag7>::new("(TTI){#[(TT)T]=1}").unwrap();
+        let after = before.simplify_with(Settings::default());
+        assert!(before.equivalent(&after), "{before} vs {after}");
+    }
+}